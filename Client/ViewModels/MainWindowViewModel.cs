@@ -31,8 +31,8 @@ public class MainWindowViewModel : ViewModelBase
         SpacetimeDb ??= new SpacetimeDB(Callback, TickCallback, connection =>
         {
             connection.SubscriptionBuilder().OnApplied(_ => { }).Subscribe([
-                "SELECT * FROM channel",
-                "SELECT * FROM member",
+                "SELECT * FROM visible_channel",
+                "SELECT * FROM visible_member",
             ]);
         });
     }
@@ -44,11 +44,11 @@ public class MainWindowViewModel : ViewModelBase
     private void Callback(DbConnection connection)
     {
         // Wait for an insert on Member to check if the member is this user, if yes add the channel
-        connection.Db.Member.OnInsert += (context, row) =>
+        connection.Db.VisibleMember.OnInsert += (context, row) =>
         {
             if (row.UserId == context.Identity!)
             {
-                var channel = context.Db.Channel.Iter().First(channel => row.ChannelId == channel.Id);
+                var channel = context.Db.VisibleChannel.Iter().First(channel => row.ChannelId == channel.Id);
                 Channels.Add(new ChannelViewModel { Name = channel.Name, Id = channel.Id });
             }
         };
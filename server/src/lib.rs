@@ -3,16 +3,50 @@
 //! WARNING: When changing anything related to the public api, remember to regenerate the spacetime bindings for the client:
 //! `spacetime generate --lang csharp --out-dir Client/ModuleBindings --project-path server`
 
+mod permissions;
 mod types;
 mod validation;
 
-use crate::types::Permission;
-use crate::validation::{validate_message, validate_name};
-use spacetimedb::{reducer, table, Identity, RangedIndex, ReducerContext, Table, Timestamp};
-use std::hash::{DefaultHasher, Hash, Hasher};
+use crate::permissions::{effective_permissions, highest_position, member_roles, outranks};
+use crate::types::{LegacyPermission, MessageKind, OverwriteTarget, Permission, TwoUsers};
+use crate::validation::{validate_emoji, validate_message, validate_name};
+use spacetimedb::rand::Rng;
+use spacetimedb::{
+    reducer, table, view, Identity, ReducerContext, ScheduleAt, Table, TimeDuration, Timestamp,
+    ViewContext,
+};
+use std::time::Duration;
 
 pub type ReducerResult = Result<(), String>;
 
+/// How often the threads are checked for inactivity
+const THREAD_ARCHIVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Longest inactivity a thread can be configured to wait before being archived
+const MAX_THREAD_AUTO_ARCHIVE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Longest time a guild invite can be valid for
+const MAX_INVITE_DURATION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// How often the expired guild invites are deleted
+const INVITE_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Mention that notifies every member of a guild, it requires `Permission::MentionEveryone`
+const EVERYONE_MENTION: &str = "@everyone";
+
+/// How often the rows referencing missing channels are looked for
+const CHANNEL_ORPHAN_CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Name of the role every member of a guild holds
+const DEFAULT_ROLE_NAME: &str = "@everyone";
+
+/// Length of the generated invite codes
+const INVITE_CODE_LENGTH: usize = 8;
+
+/// Characters used to generate the invite codes
+const INVITE_CODE_CHARACTERS: &[u8] =
+    b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
 /// Defines a user
 #[table(name = user, public)]
 pub struct User {
@@ -25,25 +59,39 @@ pub struct User {
     created_at: Timestamp,
 }
 
-/// Defines a friendship relation between two users
-#[table(name = friend, public)]
+/// Defines a friendship relation between two users, the users are ordered as in [`TwoUsers`]
+#[table(name = friendship, public, index(name = users, btree(columns = [user_a, user_b])))]
 pub struct Friend {
-    #[unique]
-    hash: u64,
     user_a: Identity,
     user_b: Identity,
 }
 
-#[table(name = friend_request, public)]
+/// Defines a friendship request between two users, the requester is one of the two
+///
+/// The table is private, clients receive their requests through `incoming_friend_request` and `outgoing_friend_request`
+#[table(name = friendship_request, index(name = users, btree(columns = [user_a, user_b])))]
 pub struct FriendRequest {
-    #[unique]
-    hash: u64,
     user_a: Identity,
+    #[index(btree)]
     user_b: Identity,
+    #[index(btree)]
+    requester: Identity,
+}
+
+/// Defines a user that blocked another user
+///
+/// The table is private, clients receive the users they blocked through `blocked_user`
+#[table(name = user_block, index(name = blocker_and_blocked, btree(columns = [blocker, blocked])))]
+pub struct UserBlock {
+    blocker: Identity,
+    blocked: Identity,
+    created_at: Timestamp,
 }
 
 /// Defines a channel that has a name and the owner whom is a member of the channel
-#[table(name = channel, public)]
+///
+/// The table is private, clients receive the channels they're members of through `visible_channel`
+#[table(name = channel)]
 pub struct Channel {
     #[primary_key]
     #[auto_inc]
@@ -55,24 +103,75 @@ pub struct Channel {
 }
 
 /// Defines a member, aka a user and which channel he's in
-#[table(name = member, public)]
+///
+/// The table is private, clients receive the members of their channels through `visible_member`
+#[table(name = channel_member, index(name = user_and_channel, btree(columns = [user_id, channel_id])))]
 pub struct Member {
-    #[unique]
-    hash: u64,
     user_id: Identity,
+    #[index(btree)]
+    channel_id: i128,
+}
+
+/// Defines an invite to a channel that the invited user can accept or decline
+///
+/// The table is private, clients receive the invites through `visible_channel_invite`
+#[table(name = channel_invite, index(name = channel_and_invitee, btree(columns = [channel_id, invitee])))]
+pub struct ChannelInvite {
     channel_id: i128,
+    #[index(btree)]
+    invitee: Identity,
+    inviter: Identity,
+    created_at: Timestamp,
 }
 
 /// Defines a message sent in a specific channel
-#[table(name = message, public)]
+///
+/// The table is private, clients receive the messages of their channels through `visible_message`
+#[table(name = channel_message)]
 pub struct Message {
     #[primary_key]
     #[auto_inc]
     id: i128,
-    sender: String,
+    sender: Identity,
+    #[index(btree)]
     channel_id: i128,
     sent: Timestamp,
     text: String,
+    edited_at: Option<Timestamp>,
+    deleted_by: Option<Identity>,
+    deleted_at: Option<Timestamp>,
+    reply_to: Option<i128>,
+}
+
+/// Defines a conversation between two friends, the users are ordered as in [`TwoUsers`]
+///
+/// The table is private, clients receive their conversations through `visible_direct_conversation`
+#[table(name = direct_conversation, index(name = users, btree(columns = [user_a, user_b])))]
+pub struct DirectConversation {
+    #[primary_key]
+    #[auto_inc]
+    id: i128,
+    user_a: Identity,
+    #[index(btree)]
+    user_b: Identity,
+    created_at: Timestamp,
+    /// The conversation is frozen when the users aren't friends anymore, the messages stay readable
+    frozen: bool,
+}
+
+/// Defines a message sent in a direct conversation
+///
+/// The table is private, clients receive the messages of their conversations through `visible_direct_message`
+#[table(name = direct_message)]
+pub struct DirectMessage {
+    #[primary_key]
+    #[auto_inc]
+    id: i128,
+    sender: Identity,
+    #[index(btree)]
+    conversation_id: i128,
+    sent: Timestamp,
+    text: String,
 }
 
 /// Defines a guild that may contain channels and has a permission system and an owner whom is a member
@@ -84,6 +183,12 @@ pub struct Guild {
     name: String,
     created_at: Timestamp,
     owner: Identity,
+    /// Whether users can join the guild without an invite, guilds created before invites existed stay open
+    #[default(true)]
+    open: bool,
+    /// The role every member holds implicitly, it can't be removed
+    #[default(0)]
+    default_role_id: i128,
 }
 
 /// Defines a guild channel
@@ -92,6 +197,7 @@ pub struct GuildChannel {
     #[primary_key]
     #[auto_inc]
     id: i128,
+    #[index(btree)]
     guild_id: i128,
     name: String,
     created_at: Timestamp,
@@ -101,7 +207,83 @@ pub struct GuildChannel {
 #[table(name = guild_member, public, index(name = user_and_guild, btree(columns = [user_id, guild_id])))]
 pub struct GuildMember {
     user_id: Identity,
+    #[index(btree)]
+    guild_id: i128,
+}
+
+/// Defines the display color of a guild member, taken from the highest role with a color
+///
+/// Members without a row have no color, the row is updated in place when the color changes
+#[table(name = guild_member_color, public, index(name = user_and_guild, btree(columns = [user_id, guild_id])))]
+pub struct GuildMemberColor {
+    #[primary_key]
+    #[auto_inc]
+    id: i128,
+    user_id: Identity,
+    #[index(btree)]
+    guild_id: i128,
+    color: u32,
+}
+
+/// Defines a user banned from a guild, banned users can't join the guild again
+#[table(name = guild_ban, public, index(name = guild_and_user, btree(columns = [guild_id, user_id])))]
+pub struct GuildBan {
+    guild_id: i128,
+    user_id: Identity,
+    banned_by: Identity,
+    created_at: Timestamp,
+}
+
+/// Defines an invite to join a guild
+///
+/// The table is private, clients receive the invites they can manage through `visible_guild_invite`
+#[table(name = guild_invite)]
+pub struct GuildInvite {
+    #[primary_key]
+    code: String,
+    #[index(btree)]
     guild_id: i128,
+    creator: Identity,
+    created_at: Timestamp,
+    expires_at: Option<Timestamp>,
+    /// The invite is deleted after being used this many times
+    max_uses: Option<u32>,
+    uses: u32,
+}
+
+/// Schedules the deletion of expired guild invites
+#[table(name = invite_cleanup_schedule, scheduled(delete_expired_invites))]
+pub struct InviteCleanupSchedule {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+}
+
+/// Schedules a single run of the migrations that weren't applied yet
+#[table(name = migration_schedule, scheduled(run_migrations))]
+pub struct MigrationSchedule {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+}
+
+/// Defines a migration that was applied to the database, by name
+#[table(name = applied_migration)]
+pub struct AppliedMigration {
+    #[primary_key]
+    name: String,
+    applied_at: Timestamp,
+}
+
+/// Schedules the check for rows referencing missing channels
+#[table(name = channel_orphan_check_schedule, scheduled(check_channel_orphans))]
+pub struct ChannelOrphanCheckSchedule {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
 }
 
 /// Defines a role in the guild that has a name and a color
@@ -110,15 +292,19 @@ pub struct GuildRole {
     #[primary_key]
     #[auto_inc]
     id: i128,
+    #[index(btree)]
     guild_id: i128,
     name: String,
     // we use 32 bits per color to enable clients to use 10-bit depth colors
     // transparency is *not* supported
     color: u32,
+    /// Roles with a higher position outrank the ones below them, the default role is always at 0
+    #[default(0)]
+    position: u32,
 }
 
 /// Defines a permission for a role
-#[table(name = guild_permission, public, index(name = role, btree(columns = [role_id])))]
+#[table(name = role_permission, public, index(name = role, btree(columns = [role_id])))]
 pub struct GuildPermission {
     #[primary_key]
     #[auto_inc]
@@ -127,6 +313,19 @@ pub struct GuildPermission {
     permission: Permission,
 }
 
+/// Allows or denies a permission in a guild channel for a role or a member, overriding the permissions of the roles
+#[table(name = channel_overwrite, public)]
+pub struct ChannelOverwrite {
+    #[primary_key]
+    #[auto_inc]
+    id: i128,
+    #[index(btree)]
+    channel_id: i128,
+    target: OverwriteTarget,
+    permission: Permission,
+    allow: bool,
+}
+
 /// Assigns a role to a member
 #[table(name = guild_member_role, public, index(name = user_and_role, btree(columns = [user_id, role_id])), index(name = role_and_user, btree(columns = [role_id, user_id])))]
 pub struct GuildMemberRole {
@@ -134,8 +333,111 @@ pub struct GuildMemberRole {
     role_id: i128,
 }
 
-#[table(name = guild_message, public)]
+/// Defines a message sent in a guild channel
+///
+/// The table is private, clients receive the messages they can read through `visible_guild_message`
+#[table(name = guild_channel_message)]
 pub struct GuildMessage {
+    #[primary_key]
+    #[auto_inc]
+    id: i128,
+    sender: Identity,
+    #[index(btree)]
+    channel_id: i128,
+    sent: Timestamp,
+    text: String,
+    edited_at: Option<Timestamp>,
+    deleted_by: Option<Identity>,
+    deleted_at: Option<Timestamp>,
+    reply_to: Option<i128>,
+    /// The thread the message was sent in, `None` if it was sent directly in the channel
+    thread_id: Option<i128>,
+}
+
+/// Defines a thread started from a guild message
+///
+/// The table is private, clients receive the threads they can read through `visible_guild_thread`
+#[table(name = guild_thread)]
+pub struct GuildThread {
+    #[primary_key]
+    #[auto_inc]
+    id: i128,
+    #[unique]
+    parent_message_id: i128,
+    #[index(btree)]
+    channel_id: i128,
+    name: String,
+    creator: Identity,
+    created_at: Timestamp,
+    last_activity: Timestamp,
+    /// How long the thread can stay without new messages before being archived
+    auto_archive_after: TimeDuration,
+    archived: bool,
+}
+
+/// Defines a member of a thread
+///
+/// The table is private, clients receive the members of the threads they can read through `visible_guild_thread_member`
+#[table(name = guild_thread_member, index(name = thread_and_user, btree(columns = [thread_id, user_id])))]
+pub struct GuildThreadMember {
+    thread_id: i128,
+    user_id: Identity,
+}
+
+/// Schedules the archiving of inactive threads
+#[table(name = thread_archive_schedule, scheduled(archive_inactive_threads))]
+pub struct ThreadArchiveSchedule {
+    #[primary_key]
+    #[auto_inc]
+    scheduled_id: u64,
+    scheduled_at: ScheduleAt,
+}
+
+/// Defines a previous version of an edited message, the text is the one replaced by the edit
+///
+/// The table is private, clients receive the revisions they can read through `visible_message_revision`
+#[table(name = message_revision, index(name = kind_and_message, btree(columns = [kind, message_id])))]
+pub struct MessageRevision {
+    #[primary_key]
+    #[auto_inc]
+    id: i128,
+    kind: MessageKind,
+    message_id: i128,
+    text: String,
+    edited_at: Timestamp,
+}
+
+/// Defines a reaction of a user to a message, each user can react with the same emoji only once
+///
+/// The table is private, clients receive the reactions they can read through `visible_reaction`
+#[table(name = reaction, index(name = message_user_and_emoji, btree(columns = [kind, message_id, user_id, emoji])))]
+pub struct Reaction {
+    kind: MessageKind,
+    message_id: i128,
+    user_id: Identity,
+    emoji: String,
+    created_at: Timestamp,
+}
+
+/// Layout of the original `message` table, which stored the name of the sender
+///
+/// Rows are moved into `channel_message` by [`migrate_message_senders`]
+#[table(name = message)]
+pub struct LegacyMessage {
+    #[primary_key]
+    #[auto_inc]
+    id: i128,
+    sender: String,
+    channel_id: i128,
+    sent: Timestamp,
+    text: String,
+}
+
+/// Layout of the original `guild_message` table, which stored the name of the sender
+///
+/// Rows are moved into `guild_channel_message` by [`migrate_message_senders`]
+#[table(name = guild_message)]
+pub struct LegacyGuildMessage {
     #[primary_key]
     #[auto_inc]
     id: i128,
@@ -145,8 +447,394 @@ pub struct GuildMessage {
     text: String,
 }
 
+#[view(name = visible_channel, public)]
+pub fn visible_channel(ctx: &ViewContext) -> Vec<Channel> {
+    // the channels the user was invited to are visible too
+    let invited = ctx
+        .db
+        .channel_invite()
+        .invitee()
+        .filter(ctx.sender)
+        .map(|invite| invite.channel_id);
+
+    joined_channels(ctx)
+        .into_iter()
+        .chain(invited)
+        .filter_map(|channel_id| ctx.db.channel().id().find(channel_id))
+        .collect()
+}
+
+#[view(name = visible_channel_invite, public)]
+pub fn visible_channel_invite(ctx: &ViewContext) -> Vec<ChannelInvite> {
+    // the invites received by the user and the ones pending in the channels the user is a member of
+    ctx.db
+        .channel_invite()
+        .invitee()
+        .filter(ctx.sender)
+        .chain(joined_channels(ctx).into_iter().flat_map(|channel_id| {
+            ctx.db
+                .channel_invite()
+                .channel_and_invitee()
+                .filter(channel_id)
+        }))
+        .collect()
+}
+
+#[view(name = visible_member, public)]
+pub fn visible_member(ctx: &ViewContext) -> Vec<Member> {
+    joined_channels(ctx)
+        .into_iter()
+        .flat_map(|channel_id| ctx.db.channel_member().channel_id().filter(channel_id))
+        .collect()
+}
+
+#[view(name = visible_message, public)]
+pub fn visible_message(ctx: &ViewContext) -> Vec<Message> {
+    joined_channels(ctx)
+        .into_iter()
+        .flat_map(|channel_id| ctx.db.channel_message().channel_id().filter(channel_id))
+        .collect()
+}
+
+/// Returns the ids of the channels the user is a member of
+fn joined_channels(ctx: &ViewContext) -> Vec<i128> {
+    ctx.db
+        .channel_member()
+        .user_and_channel()
+        .filter(ctx.sender)
+        .map(|member| member.channel_id)
+        .collect()
+}
+
+#[view(name = blocked_user, public)]
+pub fn blocked_user(ctx: &ViewContext) -> Vec<UserBlock> {
+    ctx.db
+        .user_block()
+        .blocker_and_blocked()
+        .filter(ctx.sender)
+        .collect()
+}
+
+#[view(name = incoming_friend_request, public)]
+pub fn incoming_friend_request(ctx: &ViewContext) -> Vec<FriendRequest> {
+    ctx.db
+        .friendship_request()
+        .users()
+        .filter(ctx.sender)
+        .chain(ctx.db.friendship_request().user_b().filter(ctx.sender))
+        .filter(|request| request.requester != ctx.sender)
+        .collect()
+}
+
+#[view(name = outgoing_friend_request, public)]
+pub fn outgoing_friend_request(ctx: &ViewContext) -> Vec<FriendRequest> {
+    ctx.db
+        .friendship_request()
+        .requester()
+        .filter(ctx.sender)
+        .collect()
+}
+
+#[view(name = visible_direct_conversation, public)]
+pub fn visible_direct_conversation(ctx: &ViewContext) -> Vec<DirectConversation> {
+    direct_conversations(ctx)
+}
+
+#[view(name = visible_direct_message, public)]
+pub fn visible_direct_message(ctx: &ViewContext) -> Vec<DirectMessage> {
+    direct_conversations(ctx)
+        .into_iter()
+        .flat_map(|conversation| {
+            ctx.db
+                .direct_message()
+                .conversation_id()
+                .filter(conversation.id)
+        })
+        .collect()
+}
+
+/// Returns the direct conversations the user is part of
+fn direct_conversations(ctx: &ViewContext) -> Vec<DirectConversation> {
+    ctx.db
+        .direct_conversation()
+        .users()
+        .filter(ctx.sender)
+        .chain(ctx.db.direct_conversation().user_b().filter(ctx.sender))
+        .collect()
+}
+
+#[view(name = visible_guild_message, public)]
+pub fn visible_guild_message(ctx: &ViewContext) -> Vec<GuildMessage> {
+    readable_guild_channels(ctx)
+        .into_iter()
+        .flat_map(|channel_id| {
+            ctx.db
+                .guild_channel_message()
+                .channel_id()
+                .filter(channel_id)
+        })
+        .collect()
+}
+
+#[view(name = visible_guild_thread, public)]
+pub fn visible_guild_thread(ctx: &ViewContext) -> Vec<GuildThread> {
+    readable_guild_channels(ctx)
+        .into_iter()
+        .flat_map(|channel_id| ctx.db.guild_thread().channel_id().filter(channel_id))
+        .collect()
+}
+
+#[view(name = visible_guild_thread_member, public)]
+pub fn visible_guild_thread_member(ctx: &ViewContext) -> Vec<GuildThreadMember> {
+    readable_guild_channels(ctx)
+        .into_iter()
+        .flat_map(|channel_id| ctx.db.guild_thread().channel_id().filter(channel_id))
+        .flat_map(|thread| {
+            ctx.db
+                .guild_thread_member()
+                .thread_and_user()
+                .filter(thread.id)
+        })
+        .collect()
+}
+
+/// Revisions are shown to the sender of the message and to whom moderates the channel
+#[view(name = visible_message_revision, public)]
+pub fn visible_message_revision(ctx: &ViewContext) -> Vec<MessageRevision> {
+    let mut revisions = Vec::new();
+
+    // revisions of the messages in the channels the user is a member of, the owner moderates the channel
+    for channel_id in joined_channels(ctx) {
+        let Some(channel) = ctx.db.channel().id().find(channel_id) else {
+            continue;
+        };
+
+        for message in ctx
+            .db
+            .channel_message()
+            .channel_id()
+            .filter(channel_id)
+            .filter(|message| channel.owner == ctx.sender || message.sender == ctx.sender)
+        {
+            revisions.extend(
+                ctx.db
+                    .message_revision()
+                    .kind_and_message()
+                    .filter((MessageKind::Channel, message.id)),
+            );
+        }
+    }
+
+    // revisions of the guild messages the user can read, the members that can manage messages moderate the channel
+    for channel_id in readable_guild_channels(ctx) {
+        let Some(channel) = ctx.db.guild_channel().id().find(channel_id) else {
+            continue;
+        };
+        let Some(guild) = ctx.db.guild().id().find(channel.guild_id) else {
+            continue;
+        };
+
+        let is_moderator = effective_permissions(ctx, ctx.sender, &guild, Some(channel_id))
+            .has(&Permission::ManageMessages);
+
+        for message in ctx
+            .db
+            .guild_channel_message()
+            .channel_id()
+            .filter(channel_id)
+            .filter(|message| is_moderator || message.sender == ctx.sender)
+        {
+            revisions.extend(
+                ctx.db
+                    .message_revision()
+                    .kind_and_message()
+                    .filter((MessageKind::Guild, message.id)),
+            );
+        }
+    }
+
+    revisions
+}
+
+#[view(name = visible_reaction, public)]
+pub fn visible_reaction(ctx: &ViewContext) -> Vec<Reaction> {
+    let mut reactions = Vec::new();
+
+    // reactions to the messages in the channels the user is a member of
+    for message in visible_message(ctx) {
+        reactions.extend(
+            ctx.db
+                .reaction()
+                .message_user_and_emoji()
+                .filter((MessageKind::Channel, message.id)),
+        );
+    }
+
+    // reactions to the guild messages the user can read
+    for message in visible_guild_message(ctx) {
+        reactions.extend(
+            ctx.db
+                .reaction()
+                .message_user_and_emoji()
+                .filter((MessageKind::Guild, message.id)),
+        );
+    }
+
+    reactions
+}
+
+#[view(name = visible_guild_invite, public)]
+pub fn visible_guild_invite(ctx: &ViewContext) -> Vec<GuildInvite> {
+    let mut invites = Vec::new();
+
+    for member in ctx.db.guild_member().user_and_guild().filter(ctx.sender) {
+        // get the guild
+        let Some(guild) = ctx.db.guild().id().find(member.guild_id) else {
+            continue;
+        };
+
+        // the owner and the administrators see every invite of the guild, the other members only the ones they created
+        let is_administrator =
+            effective_permissions(ctx, ctx.sender, &guild, None).has(&Permission::Administrator);
+        invites.extend(
+            ctx.db
+                .guild_invite()
+                .guild_id()
+                .filter(guild.id)
+                .filter(|invite| is_administrator || invite.creator == ctx.sender),
+        );
+    }
+
+    invites
+}
+
+/// Returns the ids of the channels the user can read in every guild the user is a member of
+fn readable_guild_channels(ctx: &ViewContext) -> Vec<i128> {
+    let mut channels = Vec::new();
+
+    for member in ctx.db.guild_member().user_and_guild().filter(ctx.sender) {
+        // get the guild
+        let Some(guild) = ctx.db.guild().id().find(member.guild_id) else {
+            continue;
+        };
+
+        // keep only the channels with the read permission
+        channels.extend(
+            ctx.db
+                .guild_channel()
+                .guild_id()
+                .filter(guild.id)
+                .filter(|channel| {
+                    effective_permissions(ctx, ctx.sender, &guild, Some(channel.id))
+                        .has(&Permission::Read)
+                })
+                .map(|channel| channel.id),
+        );
+    }
+
+    channels
+}
+
+/// Layout of the original `friend` table, which keyed the rows by a hash of the users
+///
+/// Rows are moved into `friendship` by [`migrate_hashed_keys`]
+#[table(name = friend)]
+pub struct LegacyFriend {
+    #[unique]
+    hash: u64,
+    user_a: Identity,
+    user_b: Identity,
+}
+
+/// Layout of the original `friend_request` table, which keyed the rows by a hash of the users
+///
+/// Rows are moved into `friendship_request` by [`migrate_hashed_keys`]
+#[table(name = friend_request)]
+pub struct LegacyFriendRequest {
+    #[unique]
+    hash: u64,
+    user_a: Identity,
+    user_b: Identity,
+}
+
+/// Layout of the original `member` table, which keyed the rows by a hash of the user and the channel
+///
+/// Rows are moved into `channel_member` by [`migrate_hashed_keys`]
+#[table(name = member)]
+pub struct LegacyMember {
+    #[unique]
+    hash: u64,
+    user_id: Identity,
+    channel_id: i128,
+}
+
+/// Layout of the original `guild_permission` table, which only had permissions for a channel by id
+///
+/// Rows are moved into `channel_overwrite` by [`migrate_channel_permissions`]
+#[table(name = guild_permission, index(name = role, btree(columns = [role_id])))]
+pub struct LegacyGuildPermission {
+    #[primary_key]
+    #[auto_inc]
+    id: i128,
+    role_id: i128,
+    permission: LegacyPermission,
+}
+
+#[reducer(init)]
+pub fn init(ctx: &ReducerContext) {
+    start_schedules(ctx);
+}
+
+/// Adds the rows of the scheduled reducers that are missing
+///
+/// `init` runs only on the first publish, so the schedules are also checked on every connection
+/// to start the ones added by an update
+fn start_schedules(ctx: &ReducerContext) {
+    // start checking for inactive threads
+    if ctx.db.thread_archive_schedule().count() == 0 {
+        ctx.db
+            .thread_archive_schedule()
+            .insert(ThreadArchiveSchedule {
+                scheduled_id: 0,
+                scheduled_at: THREAD_ARCHIVE_INTERVAL.into(),
+            });
+    }
+
+    // start deleting expired invites
+    if ctx.db.invite_cleanup_schedule().count() == 0 {
+        ctx.db
+            .invite_cleanup_schedule()
+            .insert(InviteCleanupSchedule {
+                scheduled_id: 0,
+                scheduled_at: INVITE_CLEANUP_INTERVAL.into(),
+            });
+    }
+
+    // start looking for orphan channel rows
+    if ctx.db.channel_orphan_check_schedule().count() == 0 {
+        ctx.db
+            .channel_orphan_check_schedule()
+            .insert(ChannelOrphanCheckSchedule {
+                scheduled_id: 0,
+                scheduled_at: CHANNEL_ORPHAN_CHECK_INTERVAL.into(),
+            });
+    }
+
+    // run the migrations added by an update
+    if ctx.db.applied_migration().count() < MIGRATIONS.len() as u64
+        && ctx.db.migration_schedule().count() == 0
+    {
+        ctx.db.migration_schedule().insert(MigrationSchedule {
+            scheduled_id: 0,
+            scheduled_at: ScheduleAt::Time(ctx.timestamp),
+        });
+    }
+}
+
 #[reducer(client_connected)]
 pub fn client_connected(ctx: &ReducerContext) {
+    start_schedules(ctx);
+
     // update the user if it exists
     if let Some(user) = ctx.db.user().id().find(ctx.sender) {
         ctx.db.user().id().update(User {
@@ -223,9 +911,27 @@ pub fn set_display_name(ctx: &ReducerContext, name: String) -> ReducerResult {
 
 #[reducer]
 pub fn send_message(ctx: &ReducerContext, text: String, channel: String) -> ReducerResult {
-    // get the user
-    let user = ctx
-        .db
+    insert_message(ctx, text, channel, None)
+}
+
+#[reducer]
+pub fn send_reply(
+    ctx: &ReducerContext,
+    text: String,
+    channel: String,
+    reply_to: i128,
+) -> ReducerResult {
+    insert_message(ctx, text, channel, Some(reply_to))
+}
+
+fn insert_message(
+    ctx: &ReducerContext,
+    text: String,
+    channel: String,
+    reply_to: Option<i128>,
+) -> ReducerResult {
+    // check that the user is registered
+    ctx.db
         .user()
         .id()
         .find(ctx.sender)
@@ -239,42 +945,168 @@ pub fn send_message(ctx: &ReducerContext, text: String, channel: String) -> Redu
         .find(channel)
         .ok_or("No channel found")?;
 
+    // check if the user is a member of the channel
+    if ctx
+        .db
+        .channel_member()
+        .user_and_channel()
+        .filter((ctx.sender, channel.id))
+        .count()
+        == 0
+    {
+        return Err("Not a member of the channel".into());
+    }
+
+    // check if the replied message exists in the same channel
+    if let Some(reply_to) = reply_to {
+        let replied = ctx
+            .db
+            .channel_message()
+            .id()
+            .find(reply_to)
+            .ok_or("No message found to reply to")?;
+
+        if replied.channel_id != channel.id {
+            return Err("The replied message is in another channel".into());
+        }
+    }
+
     // validate the message
     if !validate_message(&text) {
         return Err("Message content isn't valid".into());
     }
 
     // add the message
-    ctx.db.message().insert(Message {
+    ctx.db.channel_message().insert(Message {
         // it is 0 because `id` is `auto_inc` so it is changed before committing the change
         // to the database with the new id value
         id: 0,
-        sender: user.name,
+        sender: ctx.sender,
         channel_id: channel.id,
         sent: ctx.timestamp,
         text,
+        edited_at: None,
+        deleted_by: None,
+        deleted_at: None,
+        reply_to,
     });
 
     Ok(())
 }
 
 #[reducer]
-pub fn create_channel(ctx: &ReducerContext, channel_name: String) -> ReducerResult {
-    // get the user
-    let user = ctx
+pub fn edit_message(ctx: &ReducerContext, message_id: i128, text: String) -> ReducerResult {
+    // get the message
+    let message = ctx
         .db
-        .user()
+        .channel_message()
         .id()
-        .find(ctx.sender)
-        .ok_or("User is not registered")?;
+        .find(message_id)
+        .ok_or("No message found")?;
 
-    // check the channel name
-    if !validate_name(&channel_name) {
-        return Err("Channel name isn't valid".into());
+    // check if the user is the one who sent the message
+    if message.sender != ctx.sender {
+        return Err("Only the sender can edit the message".into());
     }
 
-    // check if the channel name is unique
-    if ctx.db.channel().name().find(&channel_name).is_some() {
+    // check if the message was deleted
+    if message.deleted_at.is_some() {
+        return Err("The message was deleted".into());
+    }
+
+    // validate the new content
+    if !validate_message(&text) {
+        return Err("Message content isn't valid".into());
+    }
+
+    // save the old content
+    ctx.db.message_revision().insert(MessageRevision {
+        id: 0,
+        kind: MessageKind::Channel,
+        message_id,
+        text: message.text,
+        edited_at: ctx.timestamp,
+    });
+
+    // update the message
+    ctx.db.channel_message().id().update(Message {
+        text,
+        edited_at: Some(ctx.timestamp),
+        ..message
+    });
+
+    Ok(())
+}
+
+#[reducer]
+pub fn delete_message(ctx: &ReducerContext, message_id: i128) -> ReducerResult {
+    // get the message
+    let message = ctx
+        .db
+        .channel_message()
+        .id()
+        .find(message_id)
+        .ok_or("No message found")?;
+
+    // check if the message was already deleted
+    if message.deleted_at.is_some() {
+        return Err("The message was already deleted".into());
+    }
+
+    // the sender can always delete its own messages, otherwise the user must be the owner of the channel
+    if message.sender != ctx.sender {
+        let channel = ctx
+            .db
+            .channel()
+            .id()
+            .find(message.channel_id)
+            .ok_or("No channel found")?;
+
+        if channel.owner != ctx.sender {
+            return Err(
+                "Only the sender or the owner of the channel can delete the message".into(),
+            );
+        }
+    }
+
+    // delete the previous versions and the reactions so nothing of the content is left
+    ctx.db
+        .message_revision()
+        .kind_and_message()
+        .delete((MessageKind::Channel, message_id));
+    ctx.db
+        .reaction()
+        .message_user_and_emoji()
+        .delete((MessageKind::Channel, message_id));
+
+    // replace the message with a tombstone so replies and pagination still find it
+    ctx.db.channel_message().id().update(Message {
+        text: String::new(),
+        deleted_by: Some(ctx.sender),
+        deleted_at: Some(ctx.timestamp),
+        ..message
+    });
+
+    Ok(())
+}
+
+#[reducer]
+pub fn create_channel(ctx: &ReducerContext, channel_name: String) -> ReducerResult {
+    // get the user
+    let user = ctx
+        .db
+        .user()
+        .id()
+        .find(ctx.sender)
+        .ok_or("User is not registered")?;
+
+    // check the channel name
+    if !validate_name(&channel_name) {
+        return Err("Channel name isn't valid".into());
+    }
+
+    // check if the channel name is unique
+    if ctx.db.channel().name().find(&channel_name).is_some() {
         return Err("A channel already exists with this name".into());
     }
 
@@ -286,15 +1118,8 @@ pub fn create_channel(ctx: &ReducerContext, channel_name: String) -> ReducerResu
         owner: ctx.sender,
     });
 
-    // compute the hash
-    let mut hasher = DefaultHasher::new();
-    user.id.hash(&mut hasher);
-    channel.id.hash(&mut hasher);
-    let hash = hasher.finish();
-
     // add the user as a member of the channel
-    ctx.db.member().insert(Member {
-        hash,
+    ctx.db.channel_member().insert(Member {
         user_id: user.id,
         channel_id: channel.id,
     });
@@ -303,7 +1128,11 @@ pub fn create_channel(ctx: &ReducerContext, channel_name: String) -> ReducerResu
 }
 
 #[reducer]
-pub fn add_user(ctx: &ReducerContext, channel: String, user_name: String) -> ReducerResult {
+pub fn invite_to_channel(
+    ctx: &ReducerContext,
+    channel: String,
+    user_name: String,
+) -> ReducerResult {
     // get the channel
     let channel = ctx
         .db
@@ -313,7 +1142,14 @@ pub fn add_user(ctx: &ReducerContext, channel: String, user_name: String) -> Red
         .ok_or("No channel found")?;
 
     // check if the requesting user is a member of the channel
-    if ctx.db.user().id().find(ctx.sender).is_none() {
+    if ctx
+        .db
+        .channel_member()
+        .user_and_channel()
+        .filter((ctx.sender, channel.id))
+        .count()
+        == 0
+    {
         return Err("Not a member of the channel".into());
     }
 
@@ -325,21 +1161,75 @@ pub fn add_user(ctx: &ReducerContext, channel: String, user_name: String) -> Red
         .find(user_name)
         .ok_or("No user found")?;
 
-    // compute the hash
-    let mut hasher = DefaultHasher::new();
-    user.id.hash(&mut hasher);
-    channel.id.hash(&mut hasher);
-    let hash = hasher.finish();
-
     // check if the user is already a member of the channel
-    if ctx.db.member().hash().find(hash).is_some() {
+    if ctx
+        .db
+        .channel_member()
+        .user_and_channel()
+        .filter((user.id, channel.id))
+        .count()
+        > 0
+    {
         return Err("Already a member of the channel".into());
     }
 
+    // check if the user was already invited
+    if ctx
+        .db
+        .channel_invite()
+        .channel_and_invitee()
+        .filter((channel.id, user.id))
+        .count()
+        > 0
+    {
+        return Err("The user was already invited".into());
+    }
+
+    // check if the user blocked or is blocked by a member of the channel
+    if has_block_in_channel(ctx, user.id, channel.id) {
+        return Err("The user has a block with a member of the channel".into());
+    }
+
+    // add the invite
+    ctx.db.channel_invite().insert(ChannelInvite {
+        channel_id: channel.id,
+        invitee: user.id,
+        inviter: ctx.sender,
+        created_at: ctx.timestamp,
+    });
+
+    Ok(())
+}
+
+#[reducer]
+pub fn accept_channel_invite(ctx: &ReducerContext, channel: String) -> ReducerResult {
+    // get the channel
+    let channel = ctx
+        .db
+        .channel()
+        .name()
+        .find(&channel)
+        .ok_or("No channel found")?;
+
+    // save the indexer
+    let index = ctx.db.channel_invite().channel_and_invitee();
+
+    // check if the user was invited
+    if index.filter((channel.id, ctx.sender)).count() == 0 {
+        return Err("No invite found".into());
+    }
+
+    // check if a block was added after the invite
+    if has_block_in_channel(ctx, ctx.sender, channel.id) {
+        return Err("You have a block with a member of the channel".into());
+    }
+
+    // remove the invite
+    index.delete((channel.id, ctx.sender));
+
     // add the user as a member of the channel
-    ctx.db.member().insert(Member {
-        hash,
-        user_id: user.id,
+    ctx.db.channel_member().insert(Member {
+        user_id: ctx.sender,
         channel_id: channel.id,
     });
 
@@ -347,7 +1237,7 @@ pub fn add_user(ctx: &ReducerContext, channel: String, user_name: String) -> Red
 }
 
 #[reducer]
-pub fn remove_user(ctx: &ReducerContext, channel: String, user_name: String) -> ReducerResult {
+pub fn decline_channel_invite(ctx: &ReducerContext, channel: String) -> ReducerResult {
     // get the channel
     let channel = ctx
         .db
@@ -356,12 +1246,39 @@ pub fn remove_user(ctx: &ReducerContext, channel: String, user_name: String) ->
         .find(&channel)
         .ok_or("No channel found")?;
 
-    // check if the requesting user is the owner of the channel
-    // if it is, it is assumed it is also a member of the channel
-    if channel.owner != ctx.sender {
-        return Err("Only the owner can remove a user".into());
+    // save the indexer
+    let index = ctx.db.channel_invite().channel_and_invitee();
+
+    // check if the user was invited
+    if index.filter((channel.id, ctx.sender)).count() == 0 {
+        return Err("No invite found".into());
     }
 
+    // remove the invite
+    index.delete((channel.id, ctx.sender));
+
+    Ok(())
+}
+
+/// Checks if the user blocked or is blocked by a member of the channel
+fn has_block_in_channel(ctx: &ReducerContext, user_id: Identity, channel_id: i128) -> bool {
+    ctx.db
+        .channel_member()
+        .channel_id()
+        .filter(channel_id)
+        .any(|member| is_blocked(ctx, user_id, member.user_id))
+}
+
+#[reducer]
+pub fn remove_user(ctx: &ReducerContext, channel: String, user_name: String) -> ReducerResult {
+    // get the channel
+    let channel = ctx
+        .db
+        .channel()
+        .name()
+        .find(&channel)
+        .ok_or("No channel found")?;
+
     // get the user
     let user = ctx
         .db
@@ -370,24 +1287,31 @@ pub fn remove_user(ctx: &ReducerContext, channel: String, user_name: String) ->
         .find(user_name)
         .ok_or("No user found")?;
 
-    // compute the hash
-    let mut hasher = DefaultHasher::new();
-    user.id.hash(&mut hasher);
-    channel.id.hash(&mut hasher);
-    let hash = hasher.finish();
+    // check if the requesting user is the owner of the channel or is leaving it
+    // if it is the owner, it is assumed it is also a member of the channel
+    if channel.owner != ctx.sender && user.id != ctx.sender {
+        return Err("Only the owner can remove another user".into());
+    }
 
     // check if the user is a member of the channel
-    if ctx.db.member().hash().find(hash).is_none() {
+    if ctx
+        .db
+        .channel_member()
+        .user_and_channel()
+        .filter((user.id, channel.id))
+        .count()
+        == 0
+    {
         return Err("The user is not a member of the channel".into());
     }
 
-    // check if the requesting user is trying to remove itself
-    if user.id == ctx.sender {
+    // check if the owner is trying to remove itself
+    if user.id == channel.owner {
         let members = ctx
             .db
-            .member()
-            .iter()
-            .filter(|member| member.channel_id == channel.id)
+            .channel_member()
+            .channel_id()
+            .filter(channel.id)
             .count();
 
         // check if there are more than one member in the channel
@@ -396,19 +1320,223 @@ pub fn remove_user(ctx: &ReducerContext, channel: String, user_name: String) ->
         }
 
         // remove channel because there are no more members in it
-        ctx.db.channel().id().delete(channel.id);
+        delete_channel_rows(ctx, channel.id);
+
+        return Ok(());
     }
 
     // remove the user
-    ctx.db.member().hash().delete(hash);
+    ctx.db
+        .channel_member()
+        .user_and_channel()
+        .delete((user.id, channel.id));
+
+    Ok(())
+}
+
+#[reducer]
+pub fn delete_channel(ctx: &ReducerContext, channel: String) -> ReducerResult {
+    // get the channel
+    let channel = ctx
+        .db
+        .channel()
+        .name()
+        .find(&channel)
+        .ok_or("No channel found")?;
+
+    // check if the requesting user is the owner of the channel
+    if channel.owner != ctx.sender {
+        return Err("Only the owner can delete the channel".into());
+    }
+
+    delete_channel_rows(ctx, channel.id);
+
+    Ok(())
+}
+
+/// Deletes the channel with its messages, members, invites and every row that depends on them
+fn delete_channel_rows(ctx: &ReducerContext, channel_id: i128) {
+    // delete the revisions and the reactions of the messages
+    for message in ctx.db.channel_message().channel_id().filter(channel_id) {
+        ctx.db
+            .message_revision()
+            .kind_and_message()
+            .delete((MessageKind::Channel, message.id));
+        ctx.db
+            .reaction()
+            .message_user_and_emoji()
+            .delete((MessageKind::Channel, message.id));
+    }
+
+    // delete the messages
+    ctx.db.channel_message().channel_id().delete(channel_id);
+
+    // delete the members and the pending invites
+    ctx.db.channel_member().channel_id().delete(channel_id);
+    ctx.db
+        .channel_invite()
+        .channel_and_invitee()
+        .delete(channel_id);
+
+    // delete the channel
+    ctx.db.channel().id().delete(channel_id);
+}
+
+/// Logs every row that references a channel or a channel message that doesn't exist anymore
+#[reducer]
+pub fn check_channel_orphans(
+    ctx: &ReducerContext,
+    _schedule: ChannelOrphanCheckSchedule,
+) -> ReducerResult {
+    // only the scheduler can check for orphans, the report is only visible in the module logs
+    if ctx.sender != ctx.identity() {
+        return Err("Only the module can check for orphan rows".into());
+    }
+
+    let channel_exists = |channel_id: i128| ctx.db.channel().id().find(channel_id).is_some();
+    let mut orphans = 0;
+
+    for message in ctx.db.channel_message().iter() {
+        if !channel_exists(message.channel_id) {
+            log::warn!(
+                "Message {} references missing channel {}",
+                message.id,
+                message.channel_id
+            );
+            orphans += 1;
+        }
+    }
+
+    for member in ctx.db.channel_member().iter() {
+        if !channel_exists(member.channel_id) {
+            log::warn!(
+                "Member {} references missing channel {}",
+                member.user_id,
+                member.channel_id
+            );
+            orphans += 1;
+        }
+    }
+
+    for invite in ctx.db.channel_invite().iter() {
+        if !channel_exists(invite.channel_id) {
+            log::warn!(
+                "Invite for {} references missing channel {}",
+                invite.invitee,
+                invite.channel_id
+            );
+            orphans += 1;
+        }
+    }
+
+    for revision in ctx
+        .db
+        .message_revision()
+        .kind_and_message()
+        .filter(MessageKind::Channel)
+    {
+        if ctx
+            .db
+            .channel_message()
+            .id()
+            .find(revision.message_id)
+            .is_none()
+        {
+            log::warn!(
+                "Revision {} references missing message {}",
+                revision.id,
+                revision.message_id
+            );
+            orphans += 1;
+        }
+    }
+
+    for reaction in ctx
+        .db
+        .reaction()
+        .message_user_and_emoji()
+        .filter(MessageKind::Channel)
+    {
+        if ctx
+            .db
+            .channel_message()
+            .id()
+            .find(reaction.message_id)
+            .is_none()
+        {
+            log::warn!(
+                "Reaction of {} references missing message {}",
+                reaction.user_id,
+                reaction.message_id
+            );
+            orphans += 1;
+        }
+    }
+
+    log::info!("Found {orphans} orphan rows referencing channels");
+
+    Ok(())
+}
+
+#[reducer]
+pub fn transfer_channel_ownership(
+    ctx: &ReducerContext,
+    channel: String,
+    user_name: String,
+) -> ReducerResult {
+    // get the channel
+    let channel = ctx
+        .db
+        .channel()
+        .name()
+        .find(&channel)
+        .ok_or("No channel found")?;
+
+    // check if the requesting user is the owner of the channel
+    if channel.owner != ctx.sender {
+        return Err("Only the owner can transfer the ownership".into());
+    }
+
+    // get the user
+    let user = ctx
+        .db
+        .user()
+        .name()
+        .find(user_name)
+        .ok_or("No user found")?;
+
+    // check if the user is already the owner
+    if user.id == ctx.sender {
+        return Err("Already the owner of the channel".into());
+    }
+
+    // check if the user is a member of the channel
+    if ctx
+        .db
+        .channel_member()
+        .user_and_channel()
+        .filter((user.id, channel.id))
+        .count()
+        == 0
+    {
+        return Err("The user is not a member of the channel".into());
+    }
+
+    // update the owner
+    ctx.db.channel().id().update(Channel {
+        owner: user.id,
+        ..channel
+    });
 
     Ok(())
 }
 
 #[reducer]
 pub fn add_friend(ctx: &ReducerContext, user_name: String) -> ReducerResult {
-    // get users
-    let user_a = ctx.db.user().id().find(ctx.sender).ok_or("No user found")?;
+    // check that the requesting user is registered
+    ctx.db.user().id().find(ctx.sender).ok_or("No user found")?;
+
+    // get the other user
     let user_b = ctx
         .db
         .user()
@@ -416,32 +1544,41 @@ pub fn add_friend(ctx: &ReducerContext, user_name: String) -> ReducerResult {
         .find(user_name)
         .ok_or("No user found")?;
 
-    // default ordering of the ids
-    let id_a = ctx.sender.min(user_b.id);
-    let id_b = ctx.sender.max(user_b.id);
+    // check if the user is trying to add itself
+    if user_b.id == ctx.sender {
+        return Err("Can't add yourself as a friend".into());
+    }
 
-    // we compute the hash of the user ids and use that has the unique id
-    // this way we avoid to iterate through each row and the database can optimize its access
-    let mut hasher = DefaultHasher::new();
-    id_a.hash(&mut hasher);
-    id_b.hash(&mut hasher);
-    let hash = hasher.finish();
+    // default ordering of the ids
+    let TwoUsers { id_a, id_b } = TwoUsers::new(ctx.sender, user_b.id);
 
     // check if the user are already friends with each other
-    if ctx.db.friend().hash().find(hash).is_some() {
+    if ctx.db.friendship().users().filter((id_a, id_b)).count() > 0 {
         return Err("Already a friend".into());
     }
 
     // check if the friendship was already requested
-    if ctx.db.friend_request().hash().find(hash).is_some() {
+    if ctx
+        .db
+        .friendship_request()
+        .users()
+        .filter((id_a, id_b))
+        .count()
+        > 0
+    {
         return Err("Already requested friendship".into());
     }
 
+    // check if one of the users blocked the other
+    if is_blocked(ctx, id_a, id_b) {
+        return Err("The user is blocked".into());
+    }
+
     // add the request to the database
-    ctx.db.friend_request().insert(FriendRequest {
-        hash,
+    ctx.db.friendship_request().insert(FriendRequest {
         user_a: id_a,
         user_b: id_b,
+        requester: ctx.sender,
     });
 
     Ok(())
@@ -449,8 +1586,10 @@ pub fn add_friend(ctx: &ReducerContext, user_name: String) -> ReducerResult {
 
 #[reducer]
 pub fn accept_friend(ctx: &ReducerContext, user_name: String) -> ReducerResult {
-    // get users
-    let user_a = ctx.db.user().id().find(ctx.sender).ok_or("No user found")?;
+    // check that the requesting user is registered
+    ctx.db.user().id().find(ctx.sender).ok_or("No user found")?;
+
+    // get the other user
     let user_b = ctx
         .db
         .user()
@@ -459,30 +1598,31 @@ pub fn accept_friend(ctx: &ReducerContext, user_name: String) -> ReducerResult {
         .ok_or("No user found")?;
 
     // default ordering of the ids
-    let id_a = ctx.sender.min(user_b.id);
-    let id_b = ctx.sender.max(user_b.id);
-
-    // compute hashes
-    let mut hasher = DefaultHasher::new();
-    id_a.hash(&mut hasher);
-    id_b.hash(&mut hasher);
-    let hash = hasher.finish();
+    let TwoUsers { id_a, id_b } = TwoUsers::new(ctx.sender, user_b.id);
 
     // check if the user are already friends with each other
-    if ctx.db.friend().hash().find(hash).is_some() {
+    if ctx.db.friendship().users().filter((id_a, id_b)).count() > 0 {
         return Err("Already a friend".into());
     }
 
-    // check if the friendship request exists
-    if ctx.db.friend_request().hash().find(hash).is_none() {
-        return Err("Friendship wasn't requested".into());
+    // get the friendship request
+    let request = ctx
+        .db
+        .friendship_request()
+        .users()
+        .filter((id_a, id_b))
+        .next()
+        .ok_or("Friendship wasn't requested")?;
+
+    // check if the user is the one who received the request
+    if request.requester == ctx.sender {
+        return Err("Only the recipient can accept the request".into());
     }
 
     // remove the request
-    ctx.db.friend_request().hash().delete(hash);
+    ctx.db.friendship_request().users().delete((id_a, id_b));
     // and add as friend
-    ctx.db.friend().insert(Friend {
-        hash,
+    ctx.db.friendship().insert(Friend {
         user_a: id_a,
         user_b: id_b,
     });
@@ -491,483 +1631,2538 @@ pub fn accept_friend(ctx: &ReducerContext, user_name: String) -> ReducerResult {
 }
 
 #[reducer]
-pub fn create_guild(ctx: &ReducerContext, name: String) -> ReducerResult {
-    // get the user
-    let user = ctx.db.user().id().find(ctx.sender).ok_or("No user found")?;
+pub fn decline_friend(ctx: &ReducerContext, user_name: String) -> ReducerResult {
+    // get the other user
+    let user_b = ctx
+        .db
+        .user()
+        .name()
+        .find(user_name)
+        .ok_or("No user found")?;
 
-    // create the guild
-    let guild = ctx.db.guild().insert(Guild {
-        // the id is auto_inc so when committed it will change to the correct id and the returning
-        // value has the correct id
-        id: 0,
-        name,
-        owner: ctx.sender,
-        created_at: ctx.timestamp,
-    });
+    // default ordering of the ids
+    let TwoUsers { id_a, id_b } = TwoUsers::new(ctx.sender, user_b.id);
 
-    // add the user as a member of the guild
-    ctx.db.guild_member().insert(GuildMember {
-        user_id: ctx.sender,
-        guild_id: guild.id,
-    });
+    // get the friendship request
+    let request = ctx
+        .db
+        .friendship_request()
+        .users()
+        .filter((id_a, id_b))
+        .next()
+        .ok_or("Friendship wasn't requested")?;
+
+    // check if the user is the one who received the request
+    if request.requester == ctx.sender {
+        return Err("Only the recipient can decline the request".into());
+    }
+
+    // remove the request
+    ctx.db.friendship_request().users().delete((id_a, id_b));
 
     Ok(())
 }
 
 #[reducer]
-pub fn join_guild(ctx: &ReducerContext, guild_id: i128) -> ReducerResult {
-    // get the user
-    let user = ctx.db.user().id().find(ctx.sender).ok_or("No user found")?;
+pub fn cancel_friend_request(ctx: &ReducerContext, user_name: String) -> ReducerResult {
+    // get the other user
+    let user_b = ctx
+        .db
+        .user()
+        .name()
+        .find(user_name)
+        .ok_or("No user found")?;
+
+    // default ordering of the ids
+    let TwoUsers { id_a, id_b } = TwoUsers::new(ctx.sender, user_b.id);
+
+    // get the friendship request
+    let request = ctx
+        .db
+        .friendship_request()
+        .users()
+        .filter((id_a, id_b))
+        .next()
+        .ok_or("Friendship wasn't requested")?;
+
+    // check if the user is the one who sent the request
+    if request.requester != ctx.sender {
+        return Err("Only the requester can cancel the request".into());
+    }
+
+    // remove the request
+    ctx.db.friendship_request().users().delete((id_a, id_b));
+
+    Ok(())
+}
+
+#[reducer]
+pub fn remove_friend(ctx: &ReducerContext, user_name: String) -> ReducerResult {
+    // get the other user
+    let user_b = ctx
+        .db
+        .user()
+        .name()
+        .find(user_name)
+        .ok_or("No user found")?;
+
+    // default ordering of the ids
+    let TwoUsers { id_a, id_b } = TwoUsers::new(ctx.sender, user_b.id);
+
+    // check if the users are friends with each other
+    if ctx.db.friendship().users().filter((id_a, id_b)).count() == 0 {
+        return Err("Not a friend".into());
+    }
+
+    remove_friendship(ctx, id_a, id_b);
+
+    Ok(())
+}
+
+/// Removes the friendship and any request between the users, freezing their conversation
+fn remove_friendship(ctx: &ReducerContext, id_a: Identity, id_b: Identity) {
+    // remove the friendship
+    ctx.db.friendship().users().delete((id_a, id_b));
+
+    // remove any request left between the users
+    ctx.db.friendship_request().users().delete((id_a, id_b));
+
+    // freeze the conversation between the users
+    let conversations: Vec<DirectConversation> = ctx
+        .db
+        .direct_conversation()
+        .users()
+        .filter((id_a, id_b))
+        .collect();
+    for conversation in conversations {
+        ctx.db
+            .direct_conversation()
+            .id()
+            .update(DirectConversation {
+                frozen: true,
+                ..conversation
+            });
+    }
+}
+
+#[reducer]
+pub fn block_user(ctx: &ReducerContext, user_name: String) -> ReducerResult {
+    // check that the requesting user is registered
+    ctx.db.user().id().find(ctx.sender).ok_or("No user found")?;
+
+    // get the other user
+    let user = ctx
+        .db
+        .user()
+        .name()
+        .find(user_name)
+        .ok_or("No user found")?;
+
+    // check if the user is trying to block itself
+    if user.id == ctx.sender {
+        return Err("Can't block yourself".into());
+    }
+
+    // check if the user was already blocked
+    if ctx
+        .db
+        .user_block()
+        .blocker_and_blocked()
+        .filter((ctx.sender, user.id))
+        .count()
+        > 0
+    {
+        return Err("User already blocked".into());
+    }
+
+    // add the block
+    ctx.db.user_block().insert(UserBlock {
+        blocker: ctx.sender,
+        blocked: user.id,
+        created_at: ctx.timestamp,
+    });
+
+    // the users can't be friends anymore
+    let TwoUsers { id_a, id_b } = TwoUsers::new(ctx.sender, user.id);
+    remove_friendship(ctx, id_a, id_b);
+
+    Ok(())
+}
+
+#[reducer]
+pub fn unblock_user(ctx: &ReducerContext, user_name: String) -> ReducerResult {
+    // get the other user
+    let user = ctx
+        .db
+        .user()
+        .name()
+        .find(user_name)
+        .ok_or("No user found")?;
+
+    // save the indexer
+    let index = ctx.db.user_block().blocker_and_blocked();
+
+    // check if the user was blocked
+    if index.filter((ctx.sender, user.id)).count() == 0 {
+        return Err("User isn't blocked".into());
+    }
+
+    // remove the block
+    index.delete((ctx.sender, user.id));
+
+    Ok(())
+}
+
+/// Checks if one of the two users blocked the other
+fn is_blocked(ctx: &ReducerContext, user_a: Identity, user_b: Identity) -> bool {
+    let index = ctx.db.user_block().blocker_and_blocked();
+
+    index.filter((user_a, user_b)).count() > 0 || index.filter((user_b, user_a)).count() > 0
+}
+
+#[reducer]
+pub fn open_direct_message(ctx: &ReducerContext, user_name: String) -> ReducerResult {
+    // check that the requesting user is registered
+    ctx.db.user().id().find(ctx.sender).ok_or("No user found")?;
+
+    // get the other user
+    let user_b = ctx
+        .db
+        .user()
+        .name()
+        .find(user_name)
+        .ok_or("No user found")?;
+
+    // check if the user is trying to talk to itself
+    if user_b.id == ctx.sender {
+        return Err("Can't open a conversation with yourself".into());
+    }
+
+    // default ordering of the ids
+    let TwoUsers { id_a, id_b } = TwoUsers::new(ctx.sender, user_b.id);
+
+    // check if the users are friends with each other
+    if ctx.db.friendship().users().filter((id_a, id_b)).count() == 0 {
+        return Err("Not a friend".into());
+    }
+
+    // check if one of the users blocked the other
+    if is_blocked(ctx, id_a, id_b) {
+        return Err("The user is blocked".into());
+    }
+
+    // check if the conversation already exists
+    if let Some(conversation) = ctx
+        .db
+        .direct_conversation()
+        .users()
+        .filter((id_a, id_b))
+        .next()
+    {
+        // the users are friends again so the conversation can continue
+        if conversation.frozen {
+            ctx.db
+                .direct_conversation()
+                .id()
+                .update(DirectConversation {
+                    frozen: false,
+                    ..conversation
+                });
+        }
+
+        return Ok(());
+    }
+
+    // create the conversation
+    ctx.db.direct_conversation().insert(DirectConversation {
+        id: 0,
+        user_a: id_a,
+        user_b: id_b,
+        created_at: ctx.timestamp,
+        frozen: false,
+    });
+
+    Ok(())
+}
+
+#[reducer]
+pub fn send_direct_message(
+    ctx: &ReducerContext,
+    conversation_id: i128,
+    text: String,
+) -> ReducerResult {
+    // get the conversation
+    let conversation = ctx
+        .db
+        .direct_conversation()
+        .id()
+        .find(conversation_id)
+        .ok_or("No conversation found")?;
+
+    // check if the user is part of the conversation
+    if conversation.user_a != ctx.sender && conversation.user_b != ctx.sender {
+        return Err("Not part of the conversation".into());
+    }
+
+    // check if the conversation was frozen
+    if conversation.frozen {
+        return Err("The conversation is frozen".into());
+    }
+
+    // check if the users are still friends with each other
+    if ctx
+        .db
+        .friendship()
+        .users()
+        .filter((conversation.user_a, conversation.user_b))
+        .count()
+        == 0
+    {
+        return Err("Not a friend".into());
+    }
+
+    // check if one of the users blocked the other
+    if is_blocked(ctx, conversation.user_a, conversation.user_b) {
+        return Err("The user is blocked".into());
+    }
+
+    // validate the message
+    if !validate_message(&text) {
+        return Err("Message content isn't valid".into());
+    }
+
+    // add the message
+    ctx.db.direct_message().insert(DirectMessage {
+        id: 0,
+        sender: ctx.sender,
+        conversation_id,
+        sent: ctx.timestamp,
+        text,
+    });
+
+    Ok(())
+}
+
+#[reducer]
+pub fn create_guild(ctx: &ReducerContext, name: String) -> ReducerResult {
+    // check that the user is registered
+    ctx.db.user().id().find(ctx.sender).ok_or("No user found")?;
+
+    // create the guild
+    let guild = ctx.db.guild().insert(Guild {
+        // the id is auto_inc so when committed it will change to the correct id and the returning
+        // value has the correct id
+        id: 0,
+        name,
+        owner: ctx.sender,
+        created_at: ctx.timestamp,
+        // guilds can be joined without an invite until the owner closes them
+        open: true,
+        default_role_id: 0,
+    });
+
+    // add the default role, every member can read and write in the channels unless overwritten
+    let default_role_id = insert_default_role(ctx, guild.id);
+    for permission in [Permission::Read, Permission::Write, Permission::React] {
+        ctx.db.role_permission().insert(GuildPermission {
+            id: 0,
+            role_id: default_role_id,
+            permission,
+        });
+    }
+
+    ctx.db.guild().id().update(Guild {
+        default_role_id,
+        ..guild
+    });
+
+    // add the user as a member of the guild
+    ctx.db.guild_member().insert(GuildMember {
+        user_id: ctx.sender,
+        guild_id: guild.id,
+    });
+
+    Ok(())
+}
+
+/// Adds the default role of the guild and returns its id
+fn insert_default_role(ctx: &ReducerContext, guild_id: i128) -> i128 {
+    ctx.db
+        .guild_role()
+        .insert(GuildRole {
+            id: 0,
+            guild_id,
+            name: DEFAULT_ROLE_NAME.into(),
+            color: 0,
+            position: 0,
+        })
+        .id
+}
+
+#[reducer]
+pub fn join_guild(ctx: &ReducerContext, guild_id: i128) -> ReducerResult {
+    // get the guild
+    let guild = ctx.db.guild().id().find(guild_id).ok_or("No guild found")?;
+
+    // check if the guild can be joined without an invite
+    if !guild.open {
+        return Err("The guild can only be joined with an invite".into());
+    }
+
+    add_guild_member(ctx, guild_id)
+}
+
+#[reducer]
+pub fn join_guild_with_invite(ctx: &ReducerContext, code: String) -> ReducerResult {
+    // get the invite
+    let invite = ctx
+        .db
+        .guild_invite()
+        .code()
+        .find(&code)
+        .ok_or("No invite found")?;
+
+    // check if the invite expired
+    if invite
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= ctx.timestamp)
+    {
+        return Err("The invite expired".into());
+    }
+
+    add_guild_member(ctx, invite.guild_id)?;
+
+    // consume the invite, removing it once it reaches the maximum number of uses
+    let uses = invite.uses + 1;
+    if invite.max_uses.is_some_and(|max_uses| uses >= max_uses) {
+        ctx.db.guild_invite().code().delete(&code);
+    } else {
+        ctx.db
+            .guild_invite()
+            .code()
+            .update(GuildInvite { uses, ..invite });
+    }
+
+    Ok(())
+}
+
+/// Adds the user as a member of the guild if the user isn't already a member or banned
+fn add_guild_member(ctx: &ReducerContext, guild_id: i128) -> ReducerResult {
+    // check that the user is registered
+    ctx.db.user().id().find(ctx.sender).ok_or("No user found")?;
+
+    // check if user is already a member of the guild
+    if ctx
+        .db
+        .guild_member()
+        .user_and_guild()
+        .filter((ctx.sender, guild_id))
+        .count()
+        == 1
+    {
+        return Err("Already a member of the guild".into());
+    }
+
+    // check if the user was banned from the guild
+    if ctx
+        .db
+        .guild_ban()
+        .guild_and_user()
+        .filter((guild_id, ctx.sender))
+        .count()
+        > 0
+    {
+        return Err("Banned from the guild".into());
+    }
+
+    // add the user as a member
+    ctx.db.guild_member().insert(GuildMember {
+        user_id: ctx.sender,
+        guild_id,
+    });
+
+    // the default role may have a color
+    update_member_color(ctx, guild_id, ctx.sender);
+
+    Ok(())
+}
+
+#[reducer]
+pub fn set_guild_open(ctx: &ReducerContext, guild_id: i128, open: bool) -> ReducerResult {
+    // get the guild
+    let guild = ctx.db.guild().id().find(guild_id).ok_or("No guild found")?;
+
+    // check if the user is the owner of the guild or has the permission
+    if !effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, None)
+        .has(&Permission::Administrator)
+    {
+        return Err("You don't have the permission to change who can join the guild".into());
+    }
+
+    // update the guild
+    ctx.db.guild().id().update(Guild { open, ..guild });
+
+    Ok(())
+}
+
+#[reducer]
+pub fn create_guild_invite(
+    ctx: &ReducerContext,
+    guild_id: i128,
+    expires_after: Option<TimeDuration>,
+    max_uses: Option<u32>,
+) -> ReducerResult {
+    // get the guild
+    let guild = ctx.db.guild().id().find(guild_id).ok_or("No guild found")?;
+
+    // check if the user is the owner of the guild or has the permission
+    if !effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, None)
+        .has(&Permission::CreateInvites)
+    {
+        return Err("You don't have the permission to create an invite".into());
+    }
+
+    // check the maximum number of uses
+    if max_uses == Some(0) {
+        return Err("An invite must have at least one use".into());
+    }
+
+    // validate the expiration
+    let expires_at = match expires_after {
+        Some(expires_after) => {
+            if expires_after <= TimeDuration::from_micros(0)
+                || expires_after > MAX_INVITE_DURATION.into()
+            {
+                return Err(
+                    "An invite must expire after a positive duration of at most 30 days".into(),
+                );
+            }
+
+            Some(
+                ctx.timestamp
+                    .checked_add(expires_after)
+                    .ok_or("The expiration of the invite is out of range")?,
+            )
+        }
+        None => None,
+    };
+
+    // generate a code that isn't used by another invite
+    let mut code = generate_invite_code(ctx);
+    while ctx.db.guild_invite().code().find(&code).is_some() {
+        code = generate_invite_code(ctx);
+    }
+
+    // add the invite
+    ctx.db.guild_invite().insert(GuildInvite {
+        code,
+        guild_id,
+        creator: ctx.sender,
+        created_at: ctx.timestamp,
+        expires_at,
+        max_uses,
+        uses: 0,
+    });
+
+    Ok(())
+}
+
+#[reducer]
+pub fn delete_guild_invite(ctx: &ReducerContext, code: String) -> ReducerResult {
+    // get the invite
+    let invite = ctx
+        .db
+        .guild_invite()
+        .code()
+        .find(&code)
+        .ok_or("No invite found")?;
+
+    // get the guild
+    let guild = ctx
+        .db
+        .guild()
+        .id()
+        .find(invite.guild_id)
+        .ok_or("No guild found")?;
+
+    // check if the user created the invite or is the owner of the guild or an administrator
+    if invite.creator != ctx.sender
+        && !effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, None)
+            .has(&Permission::Administrator)
+    {
+        return Err("Only the creator or an administrator can delete an invite".into());
+    }
+
+    // delete the invite
+    ctx.db.guild_invite().code().delete(&code);
+
+    Ok(())
+}
+
+/// Generates a random invite code
+fn generate_invite_code(ctx: &ReducerContext) -> String {
+    (0..INVITE_CODE_LENGTH)
+        .map(|_| {
+            let index = ctx.rng().gen_range(0..INVITE_CODE_CHARACTERS.len());
+            INVITE_CODE_CHARACTERS[index] as char
+        })
+        .collect()
+}
+
+#[reducer]
+pub fn delete_expired_invites(
+    ctx: &ReducerContext,
+    _schedule: InviteCleanupSchedule,
+) -> ReducerResult {
+    // only the scheduler can delete expired invites
+    if ctx.sender != ctx.identity() {
+        return Err("Only the module can delete expired invites".into());
+    }
+
+    // delete every invite that expired
+    let expired: Vec<String> = ctx
+        .db
+        .guild_invite()
+        .iter()
+        .filter(|invite| {
+            invite
+                .expires_at
+                .is_some_and(|expires_at| expires_at <= ctx.timestamp)
+        })
+        .map(|invite| invite.code)
+        .collect();
+    for code in expired {
+        ctx.db.guild_invite().code().delete(&code);
+    }
+
+    Ok(())
+}
+
+#[reducer]
+pub fn leave_guild(ctx: &ReducerContext, guild_id: i128) -> ReducerResult {
+    // get the guild
+    let guild = ctx.db.guild().id().find(guild_id).ok_or("No guild found")?;
+
+    // check if the user is the owner of the guild
+    if guild.owner == ctx.sender {
+        return Err("The owner need to transfer the ownership or delete the guild first".into());
+    }
+
+    // check if the user is a member of the guild
+    if ctx
+        .db
+        .guild_member()
+        .user_and_guild()
+        .filter((ctx.sender, guild_id))
+        .count()
+        == 0
+    {
+        return Err("Not a member of the guild".into());
+    }
+
+    remove_guild_member_rows(ctx, guild_id, ctx.sender);
+
+    Ok(())
+}
+
+#[reducer]
+pub fn kick_member(ctx: &ReducerContext, guild_id: i128, user_name: String) -> ReducerResult {
+    // get the guild
+    let guild = ctx.db.guild().id().find(guild_id).ok_or("No guild found")?;
+
+    // check if the user is the owner of the guild or has the permission
+    if !effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, None)
+        .has(&Permission::KickMembers)
+    {
+        return Err("You don't have the permission to kick a member".into());
+    }
+
+    // get the user
+    let user = ctx
+        .db
+        .user()
+        .name()
+        .find(user_name)
+        .ok_or("No user found")?;
+
+    // check if the owner is trying to kick itself
+    if user.id == guild.owner {
+        return Err("Can't kick the owner of the guild".into());
+    }
+
+    // check if the user outranks the member
+    let position = highest_position(&ctx.as_read_only(), user.id, &guild);
+    if !outranks(&ctx.as_read_only(), ctx.sender, &guild, position) {
+        return Err("You can't kick a member with a role at or above your highest role".into());
+    }
+
+    // check if the user is a member of the guild
+    if ctx
+        .db
+        .guild_member()
+        .user_and_guild()
+        .filter((user.id, guild_id))
+        .count()
+        == 0
+    {
+        return Err("The user is not a member of the guild".into());
+    }
+
+    remove_guild_member_rows(ctx, guild_id, user.id);
+
+    Ok(())
+}
+
+#[reducer]
+pub fn ban_member(ctx: &ReducerContext, guild_id: i128, user_name: String) -> ReducerResult {
+    // get the guild
+    let guild = ctx.db.guild().id().find(guild_id).ok_or("No guild found")?;
+
+    // check if the user is the owner of the guild or has the permission
+    if !effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, None)
+        .has(&Permission::BanMembers)
+    {
+        return Err("You don't have the permission to ban a member".into());
+    }
+
+    // get the user
+    let user = ctx
+        .db
+        .user()
+        .name()
+        .find(user_name)
+        .ok_or("No user found")?;
+
+    // check if the owner is trying to ban itself
+    if user.id == guild.owner {
+        return Err("Can't ban the owner of the guild".into());
+    }
+
+    // check if the user outranks the member
+    let position = highest_position(&ctx.as_read_only(), user.id, &guild);
+    if !outranks(&ctx.as_read_only(), ctx.sender, &guild, position) {
+        return Err("You can't ban a member with a role at or above your highest role".into());
+    }
+
+    // check if the user was already banned
+    if ctx
+        .db
+        .guild_ban()
+        .guild_and_user()
+        .filter((guild_id, user.id))
+        .count()
+        > 0
+    {
+        return Err("The user is already banned".into());
+    }
+
+    // add the ban
+    ctx.db.guild_ban().insert(GuildBan {
+        guild_id,
+        user_id: user.id,
+        banned_by: ctx.sender,
+        created_at: ctx.timestamp,
+    });
+
+    // remove the user from the guild, users that aren't members can be banned too
+    remove_guild_member_rows(ctx, guild_id, user.id);
+
+    Ok(())
+}
+
+#[reducer]
+pub fn unban_member(ctx: &ReducerContext, guild_id: i128, user_name: String) -> ReducerResult {
+    // get the guild
+    let guild = ctx.db.guild().id().find(guild_id).ok_or("No guild found")?;
+
+    // check if the user is the owner of the guild or has the permission
+    if !effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, None)
+        .has(&Permission::BanMembers)
+    {
+        return Err("You don't have the permission to unban a user".into());
+    }
+
+    // get the user
+    let user = ctx
+        .db
+        .user()
+        .name()
+        .find(user_name)
+        .ok_or("No user found")?;
+
+    // save the indexer
+    let index = ctx.db.guild_ban().guild_and_user();
+
+    // check if the user was banned
+    if index.filter((guild_id, user.id)).count() == 0 {
+        return Err("The user isn't banned".into());
+    }
+
+    // remove the ban
+    index.delete((guild_id, user.id));
+
+    Ok(())
+}
+
+/// Removes the user from the guild together with its roles and thread memberships in the guild
+fn remove_guild_member_rows(ctx: &ReducerContext, guild_id: i128, user_id: Identity) {
+    // remove the roles of the guild from the user
+    for role in ctx.db.guild_role().guild_id().filter(guild_id) {
+        ctx.db
+            .guild_member_role()
+            .user_and_role()
+            .delete((user_id, role.id));
+    }
+
+    // remove the user from the threads of the guild
+    for channel in ctx.db.guild_channel().guild_id().filter(guild_id) {
+        for thread in ctx.db.guild_thread().channel_id().filter(channel.id) {
+            ctx.db
+                .guild_thread_member()
+                .thread_and_user()
+                .delete((thread.id, user_id));
+        }
+    }
+
+    // remove the overwrites of the user in the channels of the guild
+    for channel in ctx.db.guild_channel().guild_id().filter(guild_id) {
+        delete_channel_overwrites(ctx, channel.id, OverwriteTarget::Member(user_id));
+    }
+
+    // remove the membership and the display color
+    ctx.db
+        .guild_member()
+        .user_and_guild()
+        .delete((user_id, guild_id));
+    ctx.db
+        .guild_member_color()
+        .user_and_guild()
+        .delete((user_id, guild_id));
+}
+
+#[reducer]
+pub fn transfer_guild_ownership(
+    ctx: &ReducerContext,
+    guild_id: i128,
+    user_name: String,
+) -> ReducerResult {
+    // get the guild
+    let guild = ctx.db.guild().id().find(guild_id).ok_or("No guild found")?;
+
+    // check if the user is the owner of the guild
+    if guild.owner != ctx.sender {
+        return Err("Only the owner can transfer the ownership".into());
+    }
+
+    // get the user
+    let user = ctx
+        .db
+        .user()
+        .name()
+        .find(user_name)
+        .ok_or("No user found")?;
+
+    // check if the user is already the owner
+    if user.id == ctx.sender {
+        return Err("Already the owner of the guild".into());
+    }
+
+    // check if the user is a member of the guild
+    if ctx
+        .db
+        .guild_member()
+        .user_and_guild()
+        .filter((user.id, guild_id))
+        .count()
+        == 0
+    {
+        return Err("The user is not a member of the guild".into());
+    }
+
+    // update the owner
+    ctx.db.guild().id().update(Guild {
+        owner: user.id,
+        ..guild
+    });
+
+    Ok(())
+}
+
+#[reducer]
+pub fn delete_guild(ctx: &ReducerContext, guild_id: i128) -> ReducerResult {
+    // get the guild
+    let guild = ctx.db.guild().id().find(guild_id).ok_or("No guild found")?;
+
+    // check if the user is the owner of the guild
+    if guild.owner != ctx.sender {
+        return Err("Only the owner can delete the guild".into());
+    }
+
+    // delete the channels and their messages
+    let channels: Vec<GuildChannel> = ctx.db.guild_channel().guild_id().filter(guild_id).collect();
+    for channel in channels {
+        delete_guild_channel_rows(ctx, channel.id);
+    }
+
+    // delete the permissions and the assignments of the roles
+    for role in ctx.db.guild_role().guild_id().filter(guild_id) {
+        ctx.db.role_permission().role().delete(role.id);
+        ctx.db.guild_member_role().role_and_user().delete(role.id);
+    }
+
+    // delete the roles
+    ctx.db.guild_role().guild_id().delete(guild_id);
+
+    // delete the members, their colors and the bans
+    ctx.db.guild_member().guild_id().delete(guild_id);
+    ctx.db.guild_member_color().guild_id().delete(guild_id);
+    ctx.db.guild_ban().guild_and_user().delete(guild_id);
+
+    // delete the invites
+    ctx.db.guild_invite().guild_id().delete(guild_id);
+
+    // delete the guild
+    ctx.db.guild().id().delete(guild_id);
+
+    Ok(())
+}
+
+#[reducer]
+pub fn create_role(
+    ctx: &ReducerContext,
+    guild_id: i128,
+    name: String,
+    color: u32,
+) -> ReducerResult {
+    // get the guild
+    let guild = ctx.db.guild().id().find(guild_id).ok_or("No guild found")?;
+
+    // check if the user is the owner of the guild or has the permission
+    if !effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, None)
+        .has(&Permission::ManageRoles)
+    {
+        return Err("You don't have the permission to create a role".into());
+    }
+
+    // move the other roles up, the new role is placed right above the default one
+    let roles: Vec<GuildRole> = ctx
+        .db
+        .guild_role()
+        .guild_id()
+        .filter(guild_id)
+        .filter(|role| role.id != guild.default_role_id)
+        .collect();
+    for role in roles {
+        ctx.db.guild_role().id().update(GuildRole {
+            position: role.position + 1,
+            ..role
+        });
+    }
+
+    // add the role
+    ctx.db.guild_role().insert(GuildRole {
+        id: 0,
+        guild_id,
+        name,
+        color,
+        position: 1,
+    });
+
+    Ok(())
+}
+
+#[reducer]
+pub fn set_role_name(ctx: &ReducerContext, role_id: i128, name: String) -> ReducerResult {
+    // get the role
+    let role = ctx
+        .db
+        .guild_role()
+        .id()
+        .find(role_id)
+        .ok_or("No role found")?;
+
+    // get the guild where the role is defined
+    let guild = ctx
+        .db
+        .guild()
+        .id()
+        .find(role.guild_id)
+        .ok_or("No guild found")?;
+
+    // check if the user is the owner of the guild or has the permission
+    if !effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, None)
+        .has(&Permission::ManageRoles)
+    {
+        return Err("You don't have the permission to change the role name".into());
+    }
+
+    // check if the user outranks the role
+    if !outranks(&ctx.as_read_only(), ctx.sender, &guild, role.position) {
+        return Err("You can't change a role at or above your highest role".into());
+    }
+
+    // update the role
+    ctx.db.guild_role().id().update(GuildRole { name, ..role });
+
+    Ok(())
+}
+
+#[reducer]
+pub fn set_role_color(ctx: &ReducerContext, role_id: i128, color: u32) -> ReducerResult {
+    // get the role
+    let role = ctx
+        .db
+        .guild_role()
+        .id()
+        .find(role_id)
+        .ok_or("No role found")?;
+
+    // get the guild where the role is defined
+    let guild = ctx
+        .db
+        .guild()
+        .id()
+        .find(role.guild_id)
+        .ok_or("No guild found")?;
+
+    // check if the user is the owner of the guild or has the permission
+    if !effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, None)
+        .has(&Permission::ManageRoles)
+    {
+        return Err("You don't have the permission to change the role color".into());
+    }
+
+    // check if the user outranks the role
+    if !outranks(&ctx.as_read_only(), ctx.sender, &guild, role.position) {
+        return Err("You can't change a role at or above your highest role".into());
+    }
+
+    // update the role
+    ctx.db.guild_role().id().update(GuildRole { color, ..role });
+
+    // update the display color of the members
+    update_member_colors(ctx, guild.id);
+
+    Ok(())
+}
+
+#[reducer]
+pub fn remove_role(ctx: &ReducerContext, role_id: i128) -> ReducerResult {
+    // get the role
+    let role = ctx
+        .db
+        .guild_role()
+        .id()
+        .find(role_id)
+        .ok_or("No role found")?;
+
+    // get the guild where the role is defined
+    let guild = ctx
+        .db
+        .guild()
+        .id()
+        .find(role.guild_id)
+        .ok_or("No guild found")?;
+
+    // check if the user is the owner of the guild or has the permission
+    if !effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, None)
+        .has(&Permission::ManageRoles)
+    {
+        return Err("You don't have the permission to remove a role".into());
+    }
+
+    // check if the role is the default one
+    if role_id == guild.default_role_id {
+        return Err("The default role can't be removed".into());
+    }
+
+    // check if the user outranks the role
+    if !outranks(&ctx.as_read_only(), ctx.sender, &guild, role.position) {
+        return Err("You can't remove a role at or above your highest role".into());
+    }
+
+    // remove the role
+    ctx.db.guild_role().id().delete(role_id);
+
+    // remove all member roles and permissions linked to the role
+    ctx.db.guild_member_role().role_and_user().delete(role_id);
+    ctx.db.role_permission().role().delete(role_id);
+
+    // remove the overwrites of the role in the channels of the guild
+    for channel in ctx.db.guild_channel().guild_id().filter(guild.id) {
+        delete_channel_overwrites(ctx, channel.id, OverwriteTarget::Role(role_id));
+    }
+
+    // move the roles above the removed one down
+    let roles: Vec<GuildRole> = ctx
+        .db
+        .guild_role()
+        .guild_id()
+        .filter(guild.id)
+        .filter(|other| other.position > role.position)
+        .collect();
+    for role in roles {
+        ctx.db.guild_role().id().update(GuildRole {
+            position: role.position - 1,
+            ..role
+        });
+    }
+
+    // update the display color of the members
+    update_member_colors(ctx, guild.id);
+
+    Ok(())
+}
+
+/// Sets the positions of the roles of the guild, the roles are given from the lowest to the highest without the default role
+#[reducer]
+pub fn reorder_roles(ctx: &ReducerContext, guild_id: i128, role_ids: Vec<i128>) -> ReducerResult {
+    // get the guild
+    let guild = ctx.db.guild().id().find(guild_id).ok_or("No guild found")?;
+
+    // check if the user is the owner of the guild or has the permission
+    if !effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, None)
+        .has(&Permission::ManageRoles)
+    {
+        return Err("You don't have the permission to reorder the roles".into());
+    }
+
+    // get the roles of the guild without the default one
+    let roles: Vec<GuildRole> = ctx
+        .db
+        .guild_role()
+        .guild_id()
+        .filter(guild_id)
+        .filter(|role| role.id != guild.default_role_id)
+        .collect();
+
+    // check if every role of the guild is given once
+    if role_ids.len() != roles.len() || !roles.iter().all(|role| role_ids.contains(&role.id)) {
+        return Err("Every role of the guild must be given once".into());
+    }
+
+    // check if the user outranks every role that moves and its new position
+    for role in &roles {
+        let position = new_role_position(&role_ids, role.id);
+
+        if position != role.position
+            && (!outranks(&ctx.as_read_only(), ctx.sender, &guild, role.position)
+                || !outranks(&ctx.as_read_only(), ctx.sender, &guild, position))
+        {
+            return Err("You can't move a role at or above your highest role".into());
+        }
+    }
+
+    // update the positions
+    for role in roles {
+        ctx.db.guild_role().id().update(GuildRole {
+            position: new_role_position(&role_ids, role.id),
+            ..role
+        });
+    }
+
+    // update the display color of the members
+    update_member_colors(ctx, guild_id);
+
+    Ok(())
+}
+
+/// Returns the position of the role in the ordered ids, the positions start at 1 right above the default role
+fn new_role_position(role_ids: &[i128], role_id: i128) -> u32 {
+    role_ids
+        .iter()
+        .position(|id| *id == role_id)
+        .map_or(0, |index| index as u32 + 1)
+}
+
+/// Sets the display color of the member to the one of the highest role with a color
+fn update_member_color(ctx: &ReducerContext, guild_id: i128, user_id: Identity) {
+    // get the guild
+    let Some(guild) = ctx.db.guild().id().find(guild_id) else {
+        return;
+    };
+
+    // get the color of the highest role with one, users that aren't members have no role
+    let color = member_roles(&ctx.as_read_only(), user_id, &guild)
+        .into_iter()
+        .filter(|role| role.color != 0)
+        .max_by_key(|role| role.position)
+        .map(|role| role.color);
+
+    let current = ctx
+        .db
+        .guild_member_color()
+        .user_and_guild()
+        .filter((user_id, guild_id))
+        .next();
+
+    match (current, color) {
+        (Some(current), Some(color)) if current.color != color => {
+            ctx.db
+                .guild_member_color()
+                .id()
+                .update(GuildMemberColor { color, ..current });
+        }
+        (Some(current), None) => {
+            ctx.db.guild_member_color().id().delete(current.id);
+        }
+        (None, Some(color)) => {
+            ctx.db.guild_member_color().insert(GuildMemberColor {
+                id: 0,
+                user_id,
+                guild_id,
+                color,
+            });
+        }
+        _ => {}
+    }
+}
+
+/// Sets the display color of every member of the guild
+fn update_member_colors(ctx: &ReducerContext, guild_id: i128) {
+    let members: Vec<GuildMember> = ctx.db.guild_member().guild_id().filter(guild_id).collect();
+
+    for member in members {
+        update_member_color(ctx, guild_id, member.user_id);
+    }
+}
+
+#[reducer]
+pub fn add_permission(
+    ctx: &ReducerContext,
+    role_id: i128,
+    permission: Permission,
+) -> ReducerResult {
+    // get the role
+    let role = ctx
+        .db
+        .guild_role()
+        .id()
+        .find(role_id)
+        .ok_or("No role found")?;
+
+    // get the guild where the role is defined
+    let guild = ctx
+        .db
+        .guild()
+        .id()
+        .find(role.guild_id)
+        .ok_or("No guild found")?;
+
+    // get the permissions of the user in the guild
+    let permissions = effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, None);
+
+    // check if the user can manage the roles of the guild
+    if !permissions.has(&Permission::ManageRoles) {
+        return Err("You don't have the permission to add permissions to a role".into());
+    }
+
+    // check if the user outranks the role
+    if !outranks(&ctx.as_read_only(), ctx.sender, &guild, role.position) {
+        return Err("You can't change a role at or above your highest role".into());
+    }
+
+    // check if the user has the permission, nobody can grant a permission they don't have
+    if !permissions.has(&permission) {
+        return Err("You can't grant a permission you don't have".into());
+    }
+
+    // check if there's already the same permission as the one currently adding
+    if ctx
+        .db
+        .role_permission()
+        .role()
+        .filter(role_id)
+        .find(|guild_permission| guild_permission.permission == permission)
+        .is_some()
+    {
+        return Err("Permission already added".into());
+    }
+
+    // add the permission
+    ctx.db.role_permission().insert(GuildPermission {
+        id: 0,
+        role_id,
+        permission,
+    });
+
+    Ok(())
+}
+
+#[reducer]
+pub fn remove_permission(ctx: &ReducerContext, permission_id: i128) -> ReducerResult {
+    // get the permission
+    let permission = ctx
+        .db
+        .role_permission()
+        .id()
+        .find(permission_id)
+        .ok_or("No permission found")?;
+
+    // get the role
+    let role = ctx
+        .db
+        .guild_role()
+        .id()
+        .find(permission.role_id)
+        .ok_or("No role found")?;
+
+    // get the guild where the role is defined
+    let guild = ctx
+        .db
+        .guild()
+        .id()
+        .find(role.guild_id)
+        .ok_or("No guild found")?;
+
+    // check if the user can manage the roles of the guild
+    if !effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, None)
+        .has(&Permission::ManageRoles)
+    {
+        return Err("You don't have the permission to remove permissions from a role".into());
+    }
+
+    // check if the user outranks the role
+    if !outranks(&ctx.as_read_only(), ctx.sender, &guild, role.position) {
+        return Err("You can't change a role at or above your highest role".into());
+    }
+
+    ctx.db.role_permission().id().delete(permission_id);
+
+    Ok(())
+}
+
+#[reducer]
+pub fn add_role_user(ctx: &ReducerContext, role_id: i128, user_name: String) -> ReducerResult {
+    // get the user
+    let user = ctx
+        .db
+        .user()
+        .name()
+        .find(user_name)
+        .ok_or("No user found")?;
+
+    // get the role
+    let role = ctx
+        .db
+        .guild_role()
+        .id()
+        .find(role_id)
+        .ok_or("No role found")?;
+
+    // get the guild where the role is defined
+    let guild = ctx
+        .db
+        .guild()
+        .id()
+        .find(role.guild_id)
+        .ok_or("No guild found")?;
+
+    // get the permissions of the user in the guild
+    let permissions = effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, None);
+
+    // check if the user is the owner of the guild or has the permission
+    if !permissions.has(&Permission::ManageRoles) {
+        return Err("You don't have the permission to add a role to a user".into());
+    }
+
+    // check if the user outranks the role
+    if !outranks(&ctx.as_read_only(), ctx.sender, &guild, role.position) {
+        return Err("You can't assign a role at or above your highest role".into());
+    }
+
+    // check if the role is the default one
+    if role_id == guild.default_role_id {
+        return Err("Every member already has the default role".into());
+    }
+
+    // check if the user is a member of the guild, banned users aren't
+    if ctx
+        .db
+        .guild_member()
+        .user_and_guild()
+        .filter((user.id, guild.id))
+        .count()
+        == 0
+    {
+        return Err("The user is not a member of the guild".into());
+    }
+
+    // check if the user has every permission of the role, nobody can grant a permission they don't have
+    if !ctx
+        .db
+        .role_permission()
+        .role()
+        .filter(role_id)
+        .all(|permission| permissions.has(&permission.permission))
+    {
+        return Err("You can't grant a role with permissions you don't have".into());
+    }
+
+    // check if user has the role
+    if ctx
+        .db
+        .guild_member_role()
+        .user_and_role()
+        .filter((user.id, role_id))
+        .count()
+        > 0
+    {
+        return Err("User has already the role".into());
+    }
+
+    ctx.db.guild_member_role().insert(GuildMemberRole {
+        user_id: user.id,
+        role_id,
+    });
+
+    // update the display color of the member
+    update_member_color(ctx, guild.id, user.id);
+
+    Ok(())
+}
+
+#[reducer]
+pub fn remove_role_user(ctx: &ReducerContext, role_id: i128, user_name: String) -> ReducerResult {
+    // get the user
+    let user = ctx
+        .db
+        .user()
+        .name()
+        .find(user_name)
+        .ok_or("No user found")?;
+
+    // get the role
+    let role = ctx
+        .db
+        .guild_role()
+        .id()
+        .find(role_id)
+        .ok_or("No role found")?;
+
+    // get the guild where the role is defined
+    let guild = ctx
+        .db
+        .guild()
+        .id()
+        .find(role.guild_id)
+        .ok_or("No guild found")?;
+
+    // check if the user is the owner of the guild or has the permission
+    if !effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, None)
+        .has(&Permission::ManageRoles)
+    {
+        return Err("You don't have the permission to remove a role from a user".into());
+    }
+
+    // check if the user outranks the role
+    if !outranks(&ctx.as_read_only(), ctx.sender, &guild, role.position) {
+        return Err("You can't remove a role at or above your highest role".into());
+    }
+
+    // check if the role is the default one
+    if role_id == guild.default_role_id {
+        return Err("The default role can't be removed from a member".into());
+    }
+
+    // save the indexer
+    let index = ctx.db.guild_member_role().user_and_role();
+
+    // check if user has the role
+    if index.filter((user.id, role_id)).count() == 0 {
+        return Err("User doesn't have the role".into());
+    }
+
+    // remove the role from the user
+    index.delete((user.id, role_id));
+
+    // update the display color of the member
+    update_member_color(ctx, guild.id, user.id);
+
+    Ok(())
+}
+
+#[reducer]
+pub fn create_guild_channel(ctx: &ReducerContext, guild_id: i128, name: String) -> ReducerResult {
+    // get the guild
+    let guild = ctx.db.guild().id().find(guild_id).ok_or("No guild found")?;
+
+    // check if the user is the owner of the guild or has the permission
+    if !effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, None)
+        .has(&Permission::ManageChannels)
+    {
+        return Err("You don't have the permission to create a channel in the guild".into());
+    }
+
+    // create the channel
+    ctx.db.guild_channel().insert(GuildChannel {
+        id: 0,
+        guild_id,
+        name,
+        created_at: ctx.timestamp,
+    });
+
+    Ok(())
+}
+
+#[reducer]
+pub fn delete_guild_channel(ctx: &ReducerContext, channel_id: i128) -> ReducerResult {
+    // get the channel
+    let channel = ctx
+        .db
+        .guild_channel()
+        .id()
+        .find(channel_id)
+        .ok_or("No channel found")?;
+
+    // get the guild
+    let guild = ctx
+        .db
+        .guild()
+        .id()
+        .find(channel.guild_id)
+        .ok_or("No guild found")?;
+
+    // check if the user is the owner of the guild or has the permission
+    if !effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, None)
+        .has(&Permission::ManageChannels)
+    {
+        return Err("You don't have the permission to delete a channel in the guild".into());
+    }
 
-    // get the guild
-    let guild = ctx.db.guild().id().find(guild_id).ok_or("No guild found")?;
+    // delete the channel
+    delete_guild_channel_rows(ctx, channel_id);
 
-    // check if user is already a member of the guild
-    if ctx
+    Ok(())
+}
+
+/// Deletes the guild channel with its messages, threads and every row that depends on them
+fn delete_guild_channel_rows(ctx: &ReducerContext, channel_id: i128) {
+    // delete the revisions and the reactions of the messages
+    for message in ctx
         .db
-        .guild_member()
-        .user_and_guild()
-        .filter((ctx.sender, guild_id))
-        .count()
-        == 1
+        .guild_channel_message()
+        .channel_id()
+        .filter(channel_id)
     {
-        return Err("Already a member of the guild".into());
+        ctx.db
+            .message_revision()
+            .kind_and_message()
+            .delete((MessageKind::Guild, message.id));
+        ctx.db
+            .reaction()
+            .message_user_and_emoji()
+            .delete((MessageKind::Guild, message.id));
     }
 
-    // add the user as a member
-    ctx.db.guild_member().insert(GuildMember {
-        user_id: ctx.sender,
-        guild_id,
-    });
+    // delete the messages, threads messages included
+    ctx.db
+        .guild_channel_message()
+        .channel_id()
+        .delete(channel_id);
+
+    // delete the members of the threads
+    for thread in ctx.db.guild_thread().channel_id().filter(channel_id) {
+        ctx.db
+            .guild_thread_member()
+            .thread_and_user()
+            .delete(thread.id);
+    }
 
-    Ok(())
+    // delete the threads
+    ctx.db.guild_thread().channel_id().delete(channel_id);
+
+    // delete the overwrites
+    ctx.db.channel_overwrite().channel_id().delete(channel_id);
+
+    // delete the channel
+    ctx.db.guild_channel().id().delete(channel_id);
 }
 
 #[reducer]
-pub fn create_role(
+pub fn set_channel_overwrite(
     ctx: &ReducerContext,
-    guild_id: i128,
-    name: String,
-    color: u32,
+    channel_id: i128,
+    target: OverwriteTarget,
+    permission: Permission,
+    allow: bool,
 ) -> ReducerResult {
+    // get the channel
+    let channel = ctx
+        .db
+        .guild_channel()
+        .id()
+        .find(channel_id)
+        .ok_or("No channel found")?;
+
     // get the guild
-    let guild = ctx.db.guild().id().find(guild_id).ok_or("No guild found")?;
+    let guild = ctx
+        .db
+        .guild()
+        .id()
+        .find(channel.guild_id)
+        .ok_or("No guild found")?;
 
-    // check if the user is the owner of the guild
-    if guild.owner != ctx.sender {
-        return Err("You must be the owner".into());
+    // get the permissions of the user in the channel
+    let permissions =
+        effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, Some(channel_id));
+
+    // check if the user is the owner of the guild or has the permission
+    if !permissions.has(&Permission::ManageChannels) {
+        return Err(
+            "You don't have the permission to change the permissions of the channel".into(),
+        );
     }
 
-    // add the role
-    ctx.db.guild_role().insert(GuildRole {
-        id: 0,
-        guild_id,
-        name,
-        color,
-    });
+    // administrators have every permission in every channel
+    if permission == Permission::Administrator {
+        return Err("The administrator permission can't be overwritten".into());
+    }
+
+    // check if the user has the permission, nobody can allow or deny a permission they don't have
+    if !permissions.has(&permission) {
+        return Err("You can't overwrite a permission you don't have".into());
+    }
+
+    // check if the target belongs to the guild
+    match target {
+        OverwriteTarget::Role(role_id) => {
+            let role = ctx
+                .db
+                .guild_role()
+                .id()
+                .find(role_id)
+                .ok_or("No role found")?;
+
+            if role.guild_id != guild.id {
+                return Err("The role is not defined in the guild".into());
+            }
+        }
+        OverwriteTarget::Member(user_id) => {
+            if ctx
+                .db
+                .guild_member()
+                .user_and_guild()
+                .filter((user_id, guild.id))
+                .count()
+                == 0
+            {
+                return Err("The user is not a member of the guild".into());
+            }
+        }
+    }
+
+    // update the overwrite of the same permission for the target, otherwise add a new one
+    let overwrite = ctx
+        .db
+        .channel_overwrite()
+        .channel_id()
+        .filter(channel_id)
+        .find(|overwrite| overwrite.target == target && overwrite.permission == permission);
+
+    match overwrite {
+        Some(overwrite) => {
+            ctx.db
+                .channel_overwrite()
+                .id()
+                .update(ChannelOverwrite { allow, ..overwrite });
+        }
+        None => {
+            ctx.db.channel_overwrite().insert(ChannelOverwrite {
+                id: 0,
+                channel_id,
+                target,
+                permission,
+                allow,
+            });
+        }
+    }
 
     Ok(())
 }
 
 #[reducer]
-pub fn set_role_name(ctx: &ReducerContext, role_id: i128, name: String) -> ReducerResult {
-    // get the role
-    let role = ctx
+pub fn remove_channel_overwrite(ctx: &ReducerContext, overwrite_id: i128) -> ReducerResult {
+    // get the overwrite
+    let overwrite = ctx
         .db
-        .guild_role()
+        .channel_overwrite()
         .id()
-        .find(role_id)
-        .ok_or("No role found")?;
+        .find(overwrite_id)
+        .ok_or("No overwrite found")?;
 
-    // get the guild where the role is defined
+    // get the channel
+    let channel = ctx
+        .db
+        .guild_channel()
+        .id()
+        .find(overwrite.channel_id)
+        .ok_or("No channel found")?;
+
+    // get the guild
     let guild = ctx
         .db
         .guild()
         .id()
-        .find(role.guild_id)
+        .find(channel.guild_id)
         .ok_or("No guild found")?;
 
-    // check if the user is the owner of the guild
-    if guild.owner != ctx.sender {
-        return Err("Only owner can change the role name".into());
+    // check if the user is the owner of the guild or has the permission
+    if !effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, Some(channel.id))
+        .has(&Permission::ManageChannels)
+    {
+        return Err(
+            "You don't have the permission to change the permissions of the channel".into(),
+        );
     }
 
-    // update the role
-    ctx.db.guild_role().insert(GuildRole { name, ..role });
+    ctx.db.channel_overwrite().id().delete(overwrite_id);
 
     Ok(())
 }
 
+/// Deletes every overwrite of the target in the channel
+fn delete_channel_overwrites(ctx: &ReducerContext, channel_id: i128, target: OverwriteTarget) {
+    let overwrites: Vec<ChannelOverwrite> = ctx
+        .db
+        .channel_overwrite()
+        .channel_id()
+        .filter(channel_id)
+        .filter(|overwrite| overwrite.target == target)
+        .collect();
+
+    for overwrite in overwrites {
+        ctx.db.channel_overwrite().id().delete(overwrite.id);
+    }
+}
+
 #[reducer]
-pub fn set_role_color(ctx: &ReducerContext, role_id: i128, color: u32) -> ReducerResult {
-    // get the role
-    let role = ctx
+pub fn send_guild_message(ctx: &ReducerContext, channel_id: i128, text: String) -> ReducerResult {
+    insert_guild_message(ctx, channel_id, None, text, None)
+}
+
+#[reducer]
+pub fn send_guild_reply(
+    ctx: &ReducerContext,
+    channel_id: i128,
+    text: String,
+    reply_to: i128,
+) -> ReducerResult {
+    insert_guild_message(ctx, channel_id, None, text, Some(reply_to))
+}
+
+fn insert_guild_message(
+    ctx: &ReducerContext,
+    channel_id: i128,
+    thread_id: Option<i128>,
+    text: String,
+    reply_to: Option<i128>,
+) -> ReducerResult {
+    // check that the user is registered
+    ctx.db.user().id().find(ctx.sender).ok_or("No user found")?;
+
+    // get the channel
+    let channel = ctx
         .db
-        .guild_role()
+        .guild_channel()
         .id()
-        .find(role_id)
-        .ok_or("No role found")?;
+        .find(channel_id)
+        .ok_or("No channel found")?;
 
-    // get the guild where the role is defined
+    // get the guild
     let guild = ctx
         .db
         .guild()
         .id()
-        .find(role.guild_id)
+        .find(channel.guild_id)
         .ok_or("No guild found")?;
 
-    // check if the user is the owner of the guild
-    if guild.owner != ctx.sender {
-        return Err("Only owner can change the role color".into());
+    // check if the user can read and write the channel
+    let permissions =
+        effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, Some(channel_id));
+    if !permissions.has(&Permission::Read) || !permissions.has(&Permission::Write) {
+        return Err("You don't have enough permission".into());
     }
 
-    // update the role
-    ctx.db.guild_role().insert(GuildRole { color, ..role });
+    // check if the replied message exists in the same channel
+    if let Some(reply_to) = reply_to {
+        let replied = ctx
+            .db
+            .guild_channel_message()
+            .id()
+            .find(reply_to)
+            .ok_or("No message found to reply to")?;
+
+        if replied.channel_id != channel_id || replied.thread_id != thread_id {
+            return Err("The replied message is in another channel or thread".into());
+        }
+    }
+
+    // validate the message
+    if !validate_message(&text) {
+        return Err("Message content isn't valid".into());
+    }
+    check_everyone_mention(ctx, &guild, channel_id, &text)?;
+
+    // add the message
+    ctx.db.guild_channel_message().insert(GuildMessage {
+        // id is auto inc
+        id: 0,
+        sender: ctx.sender,
+        channel_id,
+        sent: ctx.timestamp,
+        text,
+        edited_at: None,
+        deleted_by: None,
+        deleted_at: None,
+        reply_to,
+        thread_id,
+    });
+
+    Ok(())
+}
+
+/// Checks that the user can mention everyone in the channel when the text does
+fn check_everyone_mention(
+    ctx: &ReducerContext,
+    guild: &Guild,
+    channel_id: i128,
+    text: &str,
+) -> ReducerResult {
+    if text.contains(EVERYONE_MENTION)
+        && !effective_permissions(&ctx.as_read_only(), ctx.sender, guild, Some(channel_id))
+            .has(&Permission::MentionEveryone)
+    {
+        return Err("You don't have the permission to mention everyone".into());
+    }
 
     Ok(())
 }
 
 #[reducer]
-pub fn remove_role(ctx: &ReducerContext, role_id: i128) -> ReducerResult {
-    // get the role
-    let role = ctx
+pub fn edit_guild_message(ctx: &ReducerContext, message_id: i128, text: String) -> ReducerResult {
+    // get the message
+    let message = ctx
         .db
-        .guild_role()
+        .guild_channel_message()
         .id()
-        .find(role_id)
-        .ok_or("No role found")?;
+        .find(message_id)
+        .ok_or("No message found")?;
 
-    // get the guild where the role is defined
+    // check if the user is the one who sent the message
+    if message.sender != ctx.sender {
+        return Err("Only the sender can edit the message".into());
+    }
+
+    // check if the message was deleted
+    if message.deleted_at.is_some() {
+        return Err("The message was deleted".into());
+    }
+
+    // validate the new content
+    if !validate_message(&text) {
+        return Err("Message content isn't valid".into());
+    }
+
+    // get the guild of the message
+    let channel = ctx
+        .db
+        .guild_channel()
+        .id()
+        .find(message.channel_id)
+        .ok_or("No channel found")?;
     let guild = ctx
         .db
         .guild()
         .id()
-        .find(role.guild_id)
+        .find(channel.guild_id)
         .ok_or("No guild found")?;
 
-    // check if the user is the owner of the guild
-    if guild.owner != ctx.sender {
-        return Err("Only owner can remove a role".into());
+    // check if the user can still read and write the channel
+    let permissions =
+        effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, Some(channel.id));
+    if !permissions.has(&Permission::Read) || !permissions.has(&Permission::Write) {
+        return Err("You don't have enough permission".into());
+    }
+    check_everyone_mention(ctx, &guild, channel.id, &text)?;
+
+    // save the old content
+    ctx.db.message_revision().insert(MessageRevision {
+        id: 0,
+        kind: MessageKind::Guild,
+        message_id,
+        text: message.text,
+        edited_at: ctx.timestamp,
+    });
+
+    // update the message
+    ctx.db.guild_channel_message().id().update(GuildMessage {
+        text,
+        edited_at: Some(ctx.timestamp),
+        ..message
+    });
+
+    Ok(())
+}
+
+#[reducer]
+pub fn delete_guild_message(ctx: &ReducerContext, message_id: i128) -> ReducerResult {
+    // get the message
+    let message = ctx
+        .db
+        .guild_channel_message()
+        .id()
+        .find(message_id)
+        .ok_or("No message found")?;
+
+    // check if the message was already deleted
+    if message.deleted_at.is_some() {
+        return Err("The message was already deleted".into());
+    }
+
+    // the sender can always delete its own messages, otherwise the user must be the owner of the guild
+    // or have a role in the guild that can manage messages
+    if message.sender != ctx.sender {
+        // get the channel
+        let channel = ctx
+            .db
+            .guild_channel()
+            .id()
+            .find(message.channel_id)
+            .ok_or("No channel found")?;
+
+        // get the guild
+        let guild = ctx
+            .db
+            .guild()
+            .id()
+            .find(channel.guild_id)
+            .ok_or("No guild found")?;
+
+        if !effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, Some(channel.id))
+            .has(&Permission::ManageMessages)
+        {
+            return Err("You don't have enough permission".into());
+        }
+    }
+
+    // delete the previous versions and the reactions so nothing of the content is left
+    ctx.db
+        .message_revision()
+        .kind_and_message()
+        .delete((MessageKind::Guild, message_id));
+    ctx.db
+        .reaction()
+        .message_user_and_emoji()
+        .delete((MessageKind::Guild, message_id));
+
+    // replace the message with a tombstone so replies and pagination still find it
+    ctx.db.guild_channel_message().id().update(GuildMessage {
+        text: String::new(),
+        deleted_by: Some(ctx.sender),
+        deleted_at: Some(ctx.timestamp),
+        ..message
+    });
+
+    Ok(())
+}
+
+/// Upgrades the rows written by an earlier version of the module
+type Migration = fn(&ReducerContext) -> ReducerResult;
+
+/// The migrations of the rows written by earlier versions of the module, by name and in the order they run
+const MIGRATIONS: &[(&str, Migration)] = &[
+    ("message_senders", migrate_message_senders),
+    ("hashed_keys", migrate_hashed_keys),
+    ("channel_permissions", migrate_channel_permissions),
+    ("default_roles", migrate_default_roles),
+    ("role_positions", migrate_role_positions),
+];
+
+#[reducer]
+pub fn run_migrations(ctx: &ReducerContext, _schedule: MigrationSchedule) -> ReducerResult {
+    // only the scheduler can run the migrations
+    if ctx.sender != ctx.identity() {
+        return Err("Only the module can run the migrations".into());
+    }
+
+    for (name, migrate) in MIGRATIONS {
+        // check if the migration was already applied
+        if ctx
+            .db
+            .applied_migration()
+            .name()
+            .find(name.to_string())
+            .is_some()
+        {
+            continue;
+        }
+
+        migrate(ctx)?;
+        log::info!("Applied migration {name}");
+
+        ctx.db.applied_migration().insert(AppliedMigration {
+            name: name.to_string(),
+            applied_at: ctx.timestamp,
+        });
+    }
+
+    Ok(())
+}
+
+/// Moves the rows of the original message tables to the current ones, replacing the name of the sender with its identity
+///
+/// The names are resolved through the current `user` table, rows whose sender can't be found are left in the original tables.
+/// The moved rows get new ids, nothing references the original rows because they predate replies, revisions and reactions
+fn migrate_message_senders(ctx: &ReducerContext) -> ReducerResult {
+    let legacy_rows: Vec<LegacyMessage> = ctx.db.message().iter().collect();
+    for legacy in legacy_rows {
+        // find who sent the message
+        let Some(user) = ctx.db.user().name().find(&legacy.sender) else {
+            log::warn!(
+                "No user found for message {} sent by {}",
+                legacy.id,
+                legacy.sender
+            );
+            continue;
+        };
+
+        ctx.db.channel_message().insert(Message {
+            id: 0,
+            sender: user.id,
+            channel_id: legacy.channel_id,
+            sent: legacy.sent,
+            text: legacy.text,
+            edited_at: None,
+            deleted_by: None,
+            deleted_at: None,
+            reply_to: None,
+        });
+        ctx.db.message().id().delete(legacy.id);
+    }
+
+    let legacy_rows: Vec<LegacyGuildMessage> = ctx.db.guild_message().iter().collect();
+    for legacy in legacy_rows {
+        // find who sent the message
+        let Some(user) = ctx.db.user().name().find(&legacy.sender) else {
+            log::warn!(
+                "No user found for guild message {} sent by {}",
+                legacy.id,
+                legacy.sender
+            );
+            continue;
+        };
+
+        ctx.db.guild_channel_message().insert(GuildMessage {
+            id: 0,
+            sender: user.id,
+            channel_id: legacy.channel_id,
+            sent: legacy.sent,
+            text: legacy.text,
+            edited_at: None,
+            deleted_by: None,
+            deleted_at: None,
+            reply_to: None,
+            thread_id: None,
+        });
+        ctx.db.guild_message().id().delete(legacy.id);
+    }
+
+    Ok(())
+}
+
+/// Moves the rows of the original permission table to the current ones
+///
+/// The original permissions were all for a channel, they become overwrites that allow the permission to the role in their channel
+fn migrate_channel_permissions(ctx: &ReducerContext) -> ReducerResult {
+    let legacy_rows: Vec<LegacyGuildPermission> = ctx.db.guild_permission().iter().collect();
+    for legacy in legacy_rows {
+        let (permission, channel_id) = match legacy.permission {
+            LegacyPermission::Read(channel_id) => (Permission::Read, channel_id),
+            LegacyPermission::Write(channel_id) => (Permission::Write, channel_id),
+        };
+
+        // permissions of deleted channels are dropped
+        if ctx.db.guild_channel().id().find(channel_id).is_some() {
+            ctx.db.channel_overwrite().insert(ChannelOverwrite {
+                id: 0,
+                channel_id,
+                target: OverwriteTarget::Role(legacy.role_id),
+                permission,
+                allow: true,
+            });
+        }
+
+        ctx.db.guild_permission().id().delete(legacy.id);
+    }
+
+    Ok(())
+}
+
+/// Adds the default role to the guilds created before default roles existed
+///
+/// The roles are added without permissions, so the members keep the access they had
+fn migrate_default_roles(ctx: &ReducerContext) -> ReducerResult {
+    let guilds: Vec<Guild> = ctx
+        .db
+        .guild()
+        .iter()
+        .filter(|guild| guild.default_role_id == 0)
+        .collect();
+
+    for guild in guilds {
+        let default_role_id = insert_default_role(ctx, guild.id);
+
+        ctx.db.guild().id().update(Guild {
+            default_role_id,
+            ..guild
+        });
+    }
+
+    Ok(())
+}
+
+/// Gives distinct positions to the roles created before roles had one, keeping their order
+///
+/// The default role of each guild stays at 0, ties are broken by the id of the role
+fn migrate_role_positions(ctx: &ReducerContext) -> ReducerResult {
+    let guilds: Vec<Guild> = ctx.db.guild().iter().collect();
+
+    for guild in guilds {
+        let mut roles: Vec<GuildRole> = ctx
+            .db
+            .guild_role()
+            .guild_id()
+            .filter(guild.id)
+            .filter(|role| role.id != guild.default_role_id)
+            .collect();
+        roles.sort_by_key(|role| (role.position, role.id));
+
+        for (index, role) in roles.into_iter().enumerate() {
+            ctx.db.guild_role().id().update(GuildRole {
+                position: index as u32 + 1,
+                ..role
+            });
+        }
+
+        // update the display color of the members
+        update_member_colors(ctx, guild.id);
+    }
+
+    Ok(())
+}
+
+/// Moves the rows of the original hash keyed tables to the current ones
+///
+/// The original requests didn't store who sent them, so the first user of the request is taken as the requester.
+/// Rows already present in the current tables are dropped, so the migration can be run more than once
+fn migrate_hashed_keys(ctx: &ReducerContext) -> ReducerResult {
+    let legacy_rows: Vec<LegacyFriend> = ctx.db.friend().iter().collect();
+    for legacy in legacy_rows {
+        let TwoUsers { id_a, id_b } = TwoUsers::new(legacy.user_a, legacy.user_b);
+
+        if ctx.db.friendship().users().filter((id_a, id_b)).count() == 0 {
+            ctx.db.friendship().insert(Friend {
+                user_a: id_a,
+                user_b: id_b,
+            });
+        }
+        ctx.db.friend().hash().delete(legacy.hash);
     }
 
-    // remove the role
-    ctx.db.guild_role().id().delete(role_id);
+    let legacy_rows: Vec<LegacyFriendRequest> = ctx.db.friend_request().iter().collect();
+    for legacy in legacy_rows {
+        let TwoUsers { id_a, id_b } = TwoUsers::new(legacy.user_a, legacy.user_b);
 
-    // remove all member roles linked to the role
-    ctx.db.guild_member_role().role_and_user().delete(role_id);
+        if ctx
+            .db
+            .friendship_request()
+            .users()
+            .filter((id_a, id_b))
+            .count()
+            == 0
+        {
+            ctx.db.friendship_request().insert(FriendRequest {
+                user_a: id_a,
+                user_b: id_b,
+                requester: legacy.user_a,
+            });
+        }
+        ctx.db.friend_request().hash().delete(legacy.hash);
+    }
+
+    let legacy_rows: Vec<LegacyMember> = ctx.db.member().iter().collect();
+    for legacy in legacy_rows {
+        if ctx
+            .db
+            .channel_member()
+            .user_and_channel()
+            .filter((legacy.user_id, legacy.channel_id))
+            .count()
+            == 0
+        {
+            ctx.db.channel_member().insert(Member {
+                user_id: legacy.user_id,
+                channel_id: legacy.channel_id,
+            });
+        }
+        ctx.db.member().hash().delete(legacy.hash);
+    }
 
     Ok(())
 }
 
 #[reducer]
-pub fn add_permission(
+pub fn create_thread(
     ctx: &ReducerContext,
-    role_id: i128,
-    permission: Permission,
+    message_id: i128,
+    name: String,
+    auto_archive_after: TimeDuration,
 ) -> ReducerResult {
-    // get the role
-    let role = ctx
-        .db
-        .guild_role()
-        .id()
-        .find(role_id)
-        .ok_or("No role found")?;
+    // check that the user is registered
+    ctx.db.user().id().find(ctx.sender).ok_or("No user found")?;
 
-    // get the guild where the role is defined
-    let guild = ctx
+    // get the message
+    let message = ctx
         .db
-        .guild()
+        .guild_channel_message()
         .id()
-        .find(role.guild_id)
-        .ok_or("No guild found")?;
+        .find(message_id)
+        .ok_or("No message found")?;
 
-    // check if the user is the owner of the guild
-    if guild.owner != ctx.sender {
-        return Err("Only owner can add permissions to a role".into());
+    // threads can only be started from messages sent directly in the channel
+    if message.thread_id.is_some() {
+        return Err("Can't start a thread inside another thread".into());
     }
 
-    // check if there's already the same permission as the one currently adding
+    // check if the message was deleted
+    if message.deleted_at.is_some() {
+        return Err("The message was deleted".into());
+    }
+
+    // validate the inactivity before archiving
+    if auto_archive_after <= TimeDuration::from_micros(0)
+        || auto_archive_after > MAX_THREAD_AUTO_ARCHIVE.into()
+    {
+        return Err(
+            "The thread must be archived after a positive duration of at most 7 days".into(),
+        );
+    }
+
+    // check if the message has already a thread
     if ctx
         .db
-        .guild_permission()
-        .role()
-        .filter(role_id)
-        .find(|guild_permission| guild_permission.permission == permission)
+        .guild_thread()
+        .parent_message_id()
+        .find(message_id)
         .is_some()
     {
-        return Err("Permission already added".into());
+        return Err("The message has already a thread".into());
     }
 
-    // add the permission
-    ctx.db.guild_permission().insert(GuildPermission {
-        id: 0,
-        role_id,
-        permission,
-    });
-
-    Ok(())
-}
-
-#[reducer]
-pub fn remove_permission(ctx: &ReducerContext, permission_id: i128) -> ReducerResult {
-    // get the permission
-    let permission = ctx
-        .db
-        .guild_permission()
-        .id()
-        .find(permission_id)
-        .ok_or("No permission found")?;
+    // validate the name
+    if !validate_name(&name) {
+        return Err("Thread name isn't valid".into());
+    }
 
-    // get the role
-    let role = ctx
+    // get the channel
+    let channel = ctx
         .db
-        .guild_role()
+        .guild_channel()
         .id()
-        .find(permission.role_id)
-        .ok_or("No role found")?;
+        .find(message.channel_id)
+        .ok_or("No channel found")?;
 
-    // get the guild where the role is defined
+    // get the guild
     let guild = ctx
         .db
         .guild()
         .id()
-        .find(role.guild_id)
+        .find(channel.guild_id)
         .ok_or("No guild found")?;
 
-    // check if the user is the owner of the guild
-    if guild.owner != ctx.sender {
-        return Err("Only owner can add permissions to a role".into());
+    // check if the user can read and write the parent channel
+    let permissions =
+        effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, Some(channel.id));
+    if !permissions.has(&Permission::Read) || !permissions.has(&Permission::Write) {
+        return Err("You don't have enough permission".into());
     }
 
-    ctx.db.guild_permission().id().delete(permission_id);
+    // create the thread
+    let thread = ctx.db.guild_thread().insert(GuildThread {
+        id: 0,
+        parent_message_id: message_id,
+        channel_id: channel.id,
+        name,
+        creator: ctx.sender,
+        created_at: ctx.timestamp,
+        last_activity: ctx.timestamp,
+        auto_archive_after,
+        archived: false,
+    });
+
+    // add the creator as a member of the thread
+    ctx.db.guild_thread_member().insert(GuildThreadMember {
+        thread_id: thread.id,
+        user_id: ctx.sender,
+    });
 
     Ok(())
 }
 
 #[reducer]
-pub fn add_role_user(ctx: &ReducerContext, role_id: i128, user_name: String) -> ReducerResult {
-    // get the user
-    let user = ctx
+pub fn join_thread(ctx: &ReducerContext, thread_id: i128) -> ReducerResult {
+    // get the thread
+    let thread = ctx
         .db
-        .user()
-        .name()
-        .find(user_name)
-        .ok_or("No user found")?;
+        .guild_thread()
+        .id()
+        .find(thread_id)
+        .ok_or("No thread found")?;
 
-    // get the role
-    let role = ctx
+    // get the channel
+    let channel = ctx
         .db
-        .guild_role()
+        .guild_channel()
         .id()
-        .find(role_id)
-        .ok_or("No role found")?;
+        .find(thread.channel_id)
+        .ok_or("No channel found")?;
 
-    // get the guild where the role is defined
+    // get the guild
     let guild = ctx
         .db
         .guild()
         .id()
-        .find(role.guild_id)
+        .find(channel.guild_id)
         .ok_or("No guild found")?;
 
-    // check if the user is the owner of the guild
-    if guild.owner != ctx.sender {
-        return Err("Only owner can add role to a user".into());
+    // check if the user can read the channel of the thread
+    if !effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, Some(channel.id))
+        .has(&Permission::Read)
+    {
+        return Err("You don't have enough permission".into());
     }
 
-    // check if user has the role
+    // check if the user is already a member of the thread
     if ctx
         .db
-        .guild_member_role()
-        .user_and_role()
-        .filter((user.id, role_id))
+        .guild_thread_member()
+        .thread_and_user()
+        .filter((thread_id, ctx.sender))
         .count()
-        > 1
+        > 0
     {
-        return Err("User has already the role".into());
+        return Err("Already a member of the thread".into());
     }
 
-    ctx.db.guild_member_role().insert(GuildMemberRole {
-        user_id: user.id,
-        role_id,
+    // add the user as a member of the thread
+    ctx.db.guild_thread_member().insert(GuildThreadMember {
+        thread_id,
+        user_id: ctx.sender,
     });
 
     Ok(())
 }
 
 #[reducer]
-pub fn remove_role_user(ctx: &ReducerContext, role_id: i128, user_name: String) -> ReducerResult {
-    // get the user
-    let user = ctx
-        .db
-        .user()
-        .name()
-        .find(user_name)
-        .ok_or("No user found")?;
-
-    // get the role
-    let role = ctx
-        .db
-        .guild_role()
-        .id()
-        .find(role_id)
-        .ok_or("No role found")?;
-
-    // get the guild where the role is defined
-    let guild = ctx
-        .db
-        .guild()
-        .id()
-        .find(role.guild_id)
-        .ok_or("No guild found")?;
-
-    // check if the user is the owner of the guild
-    if guild.owner != ctx.sender {
-        return Err("Only owner can remove role to a user".into());
-    }
-
+pub fn leave_thread(ctx: &ReducerContext, thread_id: i128) -> ReducerResult {
     // save the indexer
-    let index = ctx.db.guild_member_role().user_and_role();
+    let index = ctx.db.guild_thread_member().thread_and_user();
 
-    // check if user has the role
-    if index.filter((user.id, role_id)).count() == 0 {
-        return Err("User doesn't have the role".into());
+    // check if the user is a member of the thread
+    if index.filter((thread_id, ctx.sender)).count() == 0 {
+        return Err("Not a member of the thread".into());
     }
 
-    // remove the role from the user
-    index.delete((user.id, role_id));
+    // remove the user from the thread
+    index.delete((thread_id, ctx.sender));
 
     Ok(())
 }
 
 #[reducer]
-pub fn create_guild_channel(ctx: &ReducerContext, guild_id: i128, name: String) -> ReducerResult {
-    // get the guild
-    let guild = ctx.db.guild().id().find(guild_id).ok_or("No guild found")?;
+pub fn send_thread_message(ctx: &ReducerContext, thread_id: i128, text: String) -> ReducerResult {
+    // get the thread
+    let thread = ctx
+        .db
+        .guild_thread()
+        .id()
+        .find(thread_id)
+        .ok_or("No thread found")?;
 
-    // check if the user is the owner of the guild
-    if guild.owner != ctx.sender {
-        return Err("Only owner can create a channel in the guild".into());
+    // add the message, the permissions are the ones of the parent channel
+    insert_guild_message(ctx, thread.channel_id, Some(thread_id), text, None)?;
+
+    // the user joins the thread when posting in it
+    if ctx
+        .db
+        .guild_thread_member()
+        .thread_and_user()
+        .filter((thread_id, ctx.sender))
+        .count()
+        == 0
+    {
+        ctx.db.guild_thread_member().insert(GuildThreadMember {
+            thread_id,
+            user_id: ctx.sender,
+        });
     }
 
-    // create the channel
-    ctx.db.guild_channel().insert(GuildChannel {
-        id: 0,
-        guild_id,
-        name,
-        created_at: ctx.timestamp,
+    // a new message brings the thread back if it was archived
+    ctx.db.guild_thread().id().update(GuildThread {
+        last_activity: ctx.timestamp,
+        archived: false,
+        ..thread
     });
 
     Ok(())
 }
 
 #[reducer]
-pub fn delete_guild_channel(ctx: &ReducerContext, channel_id: i128) -> ReducerResult {
-    // get the channel
-    let channel = ctx
-        .db
-        .guild_channel()
-        .id()
-        .find(channel_id)
-        .ok_or("No channel found")?;
-
-    // get the guild
-    let guild = ctx
-        .db
-        .guild()
-        .id()
-        .find(channel.guild_id)
-        .ok_or("No guild found")?;
-
-    // check if the user is the owner of the guild
-    if guild.owner != ctx.sender {
-        return Err("Only owner can delete a channel in the guild".into());
+pub fn archive_inactive_threads(
+    ctx: &ReducerContext,
+    _schedule: ThreadArchiveSchedule,
+) -> ReducerResult {
+    // only the scheduler can archive threads
+    if ctx.sender != ctx.identity() {
+        return Err("Only the module can archive threads".into());
     }
 
-    // delete the channel
-    ctx.db.guild_channel().id().delete(channel_id);
+    // archive every thread that had no activity for longer than its period
+    for thread in ctx.db.guild_thread().iter() {
+        if !thread.archived && thread.last_activity + thread.auto_archive_after <= ctx.timestamp {
+            ctx.db.guild_thread().id().update(GuildThread {
+                archived: true,
+                ..thread
+            });
+        }
+    }
 
     Ok(())
 }
 
-pub fn send_guild_message(ctx: &ReducerContext, channel_id: i128, text: String) -> ReducerResult {
-    // get the user
-    let user = ctx.db.user().id().find(ctx.sender).ok_or("No user found")?;
+#[reducer]
+pub fn add_reaction(
+    ctx: &ReducerContext,
+    kind: MessageKind,
+    message_id: i128,
+    emoji: String,
+) -> ReducerResult {
+    // check that the user is registered
+    ctx.db.user().id().find(ctx.sender).ok_or("No user found")?;
 
-    // get the channel
-    let channel = ctx
-        .db
-        .guild_channel()
-        .id()
-        .find(channel_id)
-        .ok_or("No channel found")?;
+    // validate the emoji
+    if !validate_emoji(&emoji) {
+        return Err("Emoji isn't valid".into());
+    }
 
-    // get the guild
-    let guild = ctx
-        .db
-        .guild()
-        .id()
-        .find(channel.guild_id)
-        .ok_or("No guild found")?;
+    match kind {
+        MessageKind::Channel => {
+            // get the message
+            let message = ctx
+                .db
+                .channel_message()
+                .id()
+                .find(message_id)
+                .ok_or("No message found")?;
+
+            // check if the message was deleted
+            if message.deleted_at.is_some() {
+                return Err("The message was deleted".into());
+            }
 
-    // check if the user is the owner of the guild
-    if guild.owner != ctx.sender {
-        // if he isn't
-        // get the role
-        let roles = ctx
-            .db
-            .guild_member_role()
-            .user_and_role()
-            .filter(ctx.sender);
-
-        // initialize the variable that holds the bool of whether we found the correct permission
-        let mut permission_found = false;
-
-        // for every role the user has
-        for role in roles {
-            // get the permissions of the role
-            let permissions = ctx.db.guild_permission().role().filter(role.role_id);
-
-            // check for every permission if it can write to the channel
-            if permissions
-                .filter(|permission| {
-                    if let Permission::Write(id) = permission.permission {
-                        id == channel_id
-                    } else {
-                        false
-                    }
-                })
+            // check if the user is a member of the channel
+            if ctx
+                .db
+                .channel_member()
+                .user_and_channel()
+                .filter((ctx.sender, message.channel_id))
                 .count()
-                > 0
+                == 0
             {
-                // found at least one permission
-                permission_found = true;
-
-                // exit the loop of the roles
-                break;
+                return Err("Not a member of the channel".into());
             }
         }
+        MessageKind::Guild => {
+            // get the message
+            let message = ctx
+                .db
+                .guild_channel_message()
+                .id()
+                .find(message_id)
+                .ok_or("No message found")?;
+
+            // check if the message was deleted
+            if message.deleted_at.is_some() {
+                return Err("The message was deleted".into());
+            }
 
-        // check if we found the permission
-        if !permission_found {
-            return Err("You don't have enough permission".into());
+            // get the channel
+            let channel = ctx
+                .db
+                .guild_channel()
+                .id()
+                .find(message.channel_id)
+                .ok_or("No channel found")?;
+
+            // get the guild
+            let guild = ctx
+                .db
+                .guild()
+                .id()
+                .find(channel.guild_id)
+                .ok_or("No guild found")?;
+
+            // check if the user can read and react in the channel
+            let permissions =
+                effective_permissions(&ctx.as_read_only(), ctx.sender, &guild, Some(channel.id));
+            if !permissions.has(&Permission::Read) || !permissions.has(&Permission::React) {
+                return Err("You don't have enough permission".into());
+            }
         }
     }
 
-    // validate the message
-    if !validate_message(&text) {
-        return Err("Message content isn't valid".into());
+    // check if the user already reacted with the same emoji
+    if ctx
+        .db
+        .reaction()
+        .message_user_and_emoji()
+        .filter((kind, message_id, ctx.sender, &emoji))
+        .count()
+        > 0
+    {
+        return Err("Already reacted with this emoji".into());
     }
 
-    // add the message
-    ctx.db.guild_message().insert(GuildMessage {
-        // id is auto inc
-        id: 0,
-        sender: user.name,
-        channel_id,
-        sent: ctx.timestamp,
-        text,
+    // add the reaction
+    ctx.db.reaction().insert(Reaction {
+        kind,
+        message_id,
+        user_id: ctx.sender,
+        emoji,
+        created_at: ctx.timestamp,
     });
 
     Ok(())
 }
+
+#[reducer]
+pub fn remove_reaction(
+    ctx: &ReducerContext,
+    kind: MessageKind,
+    message_id: i128,
+    emoji: String,
+) -> ReducerResult {
+    // save the indexer
+    let index = ctx.db.reaction().message_user_and_emoji();
+
+    // check if the user reacted with the emoji
+    if index.filter((kind, message_id, ctx.sender, &emoji)).count() == 0 {
+        return Err("No reaction found".into());
+    }
+
+    // remove the reaction
+    index.delete((kind, message_id, ctx.sender, &emoji));
+
+    Ok(())
+}
@@ -0,0 +1,559 @@
+//! Resolution of the permissions a user has in a guild
+
+use crate::types::{OverwriteTarget, Permission};
+use crate::{
+    channel_overwrite__view, guild_member__view, guild_member_role__view, guild_role__view,
+    role_permission__view, ChannelOverwrite, Guild, GuildMemberRole, GuildRole,
+};
+use spacetimedb::{Identity, ViewContext};
+
+/// The permissions a user has in a guild or in one of its channels
+pub struct EffectivePermissions {
+    /// Whether every permission is granted because the user is the owner or an administrator
+    all: bool,
+    granted: Vec<Permission>,
+}
+
+impl EffectivePermissions {
+    /// Resolves the permissions granted in the whole guild
+    pub fn resolve(is_owner: bool, granted: impl IntoIterator<Item = Permission>) -> Self {
+        let granted: Vec<Permission> = granted.into_iter().collect();
+        let all = is_owner || granted.contains(&Permission::Administrator);
+
+        Self { all, granted }
+    }
+
+    /// Applies a set of overwrites with the same precedence, allows win over denies
+    pub fn apply(&mut self, overwrites: impl IntoIterator<Item = (Permission, bool)>) {
+        let (allowed, denied): (Vec<_>, Vec<_>) =
+            overwrites.into_iter().partition(|(_, allow)| *allow);
+
+        // remove the denied permissions
+        self.granted
+            .retain(|permission| !denied.iter().any(|(denied, _)| denied == permission));
+
+        // add the allowed permissions
+        for (permission, _) in allowed {
+            if !self.granted.contains(&permission) {
+                self.granted.push(permission);
+            }
+        }
+    }
+
+    /// Checks if the permission is granted
+    pub fn has(&self, permission: &Permission) -> bool {
+        self.all || self.granted.contains(permission)
+    }
+}
+
+/// Access to the rows the permissions are resolved from
+pub trait PermissionRows {
+    /// Returns the roles assigned to the user in every guild
+    fn assigned_roles(&self, user: Identity) -> Vec<GuildMemberRole>;
+    /// Returns the role by id
+    fn role(&self, role_id: i128) -> Option<GuildRole>;
+    /// Checks if the user is a member of the guild
+    fn is_member(&self, user: Identity, guild_id: i128) -> bool;
+    /// Returns the permissions granted by the role in the whole guild
+    fn role_permissions(&self, role_id: i128) -> Vec<Permission>;
+    /// Returns the overwrites of the channel
+    fn channel_overwrites(&self, channel_id: i128) -> Vec<ChannelOverwrite>;
+}
+
+impl PermissionRows for ViewContext {
+    fn assigned_roles(&self, user: Identity) -> Vec<GuildMemberRole> {
+        self.db
+            .guild_member_role()
+            .user_and_role()
+            .filter(user)
+            .collect()
+    }
+
+    fn role(&self, role_id: i128) -> Option<GuildRole> {
+        self.db.guild_role().id().find(role_id)
+    }
+
+    fn is_member(&self, user: Identity, guild_id: i128) -> bool {
+        self.db
+            .guild_member()
+            .user_and_guild()
+            .filter((user, guild_id))
+            .next()
+            .is_some()
+    }
+
+    fn role_permissions(&self, role_id: i128) -> Vec<Permission> {
+        self.db
+            .role_permission()
+            .role()
+            .filter(role_id)
+            .map(|guild_permission| guild_permission.permission)
+            .collect()
+    }
+
+    fn channel_overwrites(&self, channel_id: i128) -> Vec<ChannelOverwrite> {
+        self.db
+            .channel_overwrite()
+            .channel_id()
+            .filter(channel_id)
+            .collect()
+    }
+}
+
+/// Returns the permissions the user has in the guild, or in the channel of the guild when given
+///
+/// Users that aren't members of the guild have no permission, only the roles defined in the guild are considered,
+/// every member holds the default role of the guild and the owner and the administrators have every permission.
+/// In a channel the overwrites of the default role are applied first, then the ones of the other roles and then
+/// the ones of the member
+pub fn effective_permissions(
+    rows: &impl PermissionRows,
+    user: Identity,
+    guild: &Guild,
+    channel: Option<i128>,
+) -> EffectivePermissions {
+    // check if the user is a member of the guild, the roles and the overwrites of former members are ignored
+    if !rows.is_member(user, guild.id) {
+        return EffectivePermissions::resolve(guild.owner == user, []);
+    }
+
+    // get the roles the user has in this guild
+    let roles: Vec<i128> = member_roles(rows, user, guild)
+        .into_iter()
+        .map(|role| role.id)
+        .collect();
+
+    // get the permissions granted by the roles
+    let granted = roles
+        .iter()
+        .flat_map(|role_id| rows.role_permissions(*role_id));
+
+    let mut permissions = EffectivePermissions::resolve(guild.owner == user, granted);
+
+    if let Some(channel) = channel {
+        // get the overwrites of the channel that apply to the user
+        let overwrites: Vec<_> = rows
+            .channel_overwrites(channel)
+            .into_iter()
+            .filter(|overwrite| match overwrite.target {
+                OverwriteTarget::Role(role_id) => roles.contains(&role_id),
+                OverwriteTarget::Member(user_id) => user_id == user,
+            })
+            .collect();
+
+        // the overwrites of the default role are applied first, then the ones of the other roles and of the member
+        let layer = |target: &OverwriteTarget| match target {
+            OverwriteTarget::Role(role_id) if *role_id == guild.default_role_id => 0,
+            OverwriteTarget::Role(_) => 1,
+            OverwriteTarget::Member(_) => 2,
+        };
+
+        for current in 0..3 {
+            permissions.apply(
+                overwrites
+                    .iter()
+                    .filter(|overwrite| layer(&overwrite.target) == current)
+                    .map(|overwrite| (overwrite.permission.clone(), overwrite.allow)),
+            );
+        }
+    }
+
+    permissions
+}
+
+/// Returns the roles the user has in the guild, every member holds the default role of the guild implicitly
+///
+/// Users that aren't members of the guild have no role, even if some are still assigned to them
+pub fn member_roles(rows: &impl PermissionRows, user: Identity, guild: &Guild) -> Vec<GuildRole> {
+    // check if the user is a member of the guild
+    if !rows.is_member(user, guild.id) {
+        return Vec::new();
+    }
+
+    let mut roles: Vec<GuildRole> = rows
+        .assigned_roles(user)
+        .into_iter()
+        .filter_map(|member_role| rows.role(member_role.role_id))
+        .filter(|role| role.guild_id == guild.id)
+        .collect();
+    roles.extend(rows.role(guild.default_role_id));
+
+    roles
+}
+
+/// Returns the position of the highest role the user has in the guild
+pub fn highest_position(rows: &impl PermissionRows, user: Identity, guild: &Guild) -> u32 {
+    member_roles(rows, user, guild)
+        .iter()
+        .map(|role| role.position)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Checks if the user is the owner of the guild or has a role above the position
+pub fn outranks(rows: &impl PermissionRows, user: Identity, guild: &Guild, position: u32) -> bool {
+    guild.owner == user || highest_position(rows, user, guild) > position
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spacetimedb::Timestamp;
+
+    /// Is owner, permissions granted by the role, overwrites of the default role, of the other roles and of the member,
+    /// checked permission, expected result
+    type Case = (
+        bool,
+        &'static [Permission],
+        &'static [(Permission, bool)],
+        &'static [(Permission, bool)],
+        &'static [(Permission, bool)],
+        Permission,
+        bool,
+    );
+
+    #[test]
+    fn truth_table() {
+        use Permission::*;
+
+        let cases: &[Case] = &[
+            // the owner has every permission without roles, even when denied
+            (true, &[], &[], &[], &[], Write, true),
+            (true, &[], &[], &[], &[], BanMembers, true),
+            (true, &[], &[], &[], &[(Write, false)], Write, true),
+            // members whose roles grant nothing have no permission
+            (false, &[], &[], &[], &[], Write, false),
+            (false, &[], &[], &[], &[], ManageRoles, false),
+            // the permissions of the roles apply to every channel
+            (false, &[Read, Write], &[], &[], &[], Write, true),
+            (
+                false,
+                &[ManageMessages],
+                &[],
+                &[],
+                &[],
+                ManageMessages,
+                true,
+            ),
+            // a permission doesn't grant another one
+            (false, &[Read, React], &[], &[], &[], Write, false),
+            (
+                false,
+                &[KickMembers, ManageMessages],
+                &[],
+                &[],
+                &[],
+                BanMembers,
+                false,
+            ),
+            (false, &[Read], &[], &[], &[(Write, false)], Read, true),
+            // administrators have every permission, even when denied
+            (false, &[Administrator], &[], &[], &[], BanMembers, true),
+            (false, &[Administrator], &[], &[], &[], Write, true),
+            (
+                false,
+                &[Administrator],
+                &[(Read, false)],
+                &[(Read, false)],
+                &[(Read, false)],
+                Read,
+                true,
+            ),
+            // the overwrites of the default role allow and deny in the channel
+            (false, &[], &[(Read, true)], &[], &[], Read, true),
+            (false, &[Read], &[(Read, false)], &[], &[], Read, false),
+            // the overwrites of the other roles win over the ones of the default role
+            (
+                false,
+                &[Write],
+                &[(Write, false)],
+                &[(Write, true)],
+                &[],
+                Write,
+                true,
+            ),
+            (
+                false,
+                &[],
+                &[(Write, true)],
+                &[(Write, false)],
+                &[],
+                Write,
+                false,
+            ),
+            // the overwrites of the roles allow and deny in the channel
+            (false, &[], &[], &[(Write, true)], &[], Write, true),
+            (false, &[Read], &[], &[(Read, false)], &[], Read, false),
+            // allows win over denies between roles
+            (
+                false,
+                &[Read],
+                &[],
+                &[(Read, false), (Read, true)],
+                &[],
+                Read,
+                true,
+            ),
+            // the overwrites of the member win over the ones of the roles
+            (
+                false,
+                &[],
+                &[],
+                &[(Write, true)],
+                &[(Write, false)],
+                Write,
+                false,
+            ),
+            (
+                false,
+                &[Write],
+                &[],
+                &[(Write, false)],
+                &[(Write, true)],
+                Write,
+                true,
+            ),
+            (
+                false,
+                &[Read],
+                &[(Read, false)],
+                &[],
+                &[(Read, true)],
+                Read,
+                true,
+            ),
+            (false, &[], &[], &[], &[(React, true)], React, true),
+            (false, &[React], &[], &[], &[(React, false)], React, false),
+        ];
+
+        for (
+            is_owner,
+            granted,
+            default_overwrites,
+            role_overwrites,
+            member_overwrites,
+            permission,
+            expected,
+        ) in cases
+        {
+            // the user holds role 11, the permissions are granted by it and the overwrites are in channel 100
+            let user = if *is_owner { OWNER } else { MEMBER };
+            let mut rows = two_guilds();
+            rows.assigned.push(GuildMemberRole {
+                user_id: user,
+                role_id: 11,
+            });
+            rows.permissions
+                .extend(granted.iter().map(|permission| (11, permission.clone())));
+
+            let targets = [
+                (OverwriteTarget::Role(10), default_overwrites),
+                (OverwriteTarget::Role(11), role_overwrites),
+                (OverwriteTarget::Member(user), member_overwrites),
+            ];
+            for (target, overwrites) in targets {
+                for (permission, allow) in overwrites.iter() {
+                    rows.overwrites.push(ChannelOverwrite {
+                        id: rows.overwrites.len() as i128,
+                        channel_id: 100,
+                        target,
+                        permission: permission.clone(),
+                        allow: *allow,
+                    });
+                }
+            }
+
+            assert_eq!(
+                effective_permissions(&rows, user, &guild(1, 10), Some(100)).has(permission),
+                *expected,
+                "owner: {is_owner}, granted: {granted:?}, default role: {default_overwrites:?}, roles: {role_overwrites:?}, member: {member_overwrites:?}, permission: {permission:?}"
+            );
+        }
+    }
+
+    /// Rows of a guild kept in memory
+    #[derive(Default)]
+    struct Rows {
+        assigned: Vec<GuildMemberRole>,
+        roles: Vec<GuildRole>,
+        members: Vec<(Identity, i128)>,
+        permissions: Vec<(i128, Permission)>,
+        overwrites: Vec<ChannelOverwrite>,
+    }
+
+    impl PermissionRows for Rows {
+        fn assigned_roles(&self, user: Identity) -> Vec<GuildMemberRole> {
+            self.assigned
+                .iter()
+                .filter(|member_role| member_role.user_id == user)
+                .map(|member_role| GuildMemberRole {
+                    user_id: member_role.user_id,
+                    role_id: member_role.role_id,
+                })
+                .collect()
+        }
+
+        fn role(&self, role_id: i128) -> Option<GuildRole> {
+            self.roles
+                .iter()
+                .find(|role| role.id == role_id)
+                .map(|role| GuildRole {
+                    name: role.name.clone(),
+                    ..*role
+                })
+        }
+
+        fn is_member(&self, user: Identity, guild_id: i128) -> bool {
+            self.members.contains(&(user, guild_id))
+        }
+
+        fn role_permissions(&self, role_id: i128) -> Vec<Permission> {
+            self.permissions
+                .iter()
+                .filter(|(id, _)| *id == role_id)
+                .map(|(_, permission)| permission.clone())
+                .collect()
+        }
+
+        fn channel_overwrites(&self, channel_id: i128) -> Vec<ChannelOverwrite> {
+            self.overwrites
+                .iter()
+                .filter(|overwrite| overwrite.channel_id == channel_id)
+                .map(|overwrite| ChannelOverwrite {
+                    permission: overwrite.permission.clone(),
+                    ..*overwrite
+                })
+                .collect()
+        }
+    }
+
+    const OWNER: Identity = Identity::from_byte_array([1; 32]);
+    const MEMBER: Identity = Identity::from_byte_array([2; 32]);
+
+    fn guild(id: i128, default_role_id: i128) -> Guild {
+        Guild {
+            id,
+            name: "guild".into(),
+            created_at: Timestamp::UNIX_EPOCH,
+            owner: OWNER,
+            open: true,
+            default_role_id,
+        }
+    }
+
+    fn role(id: i128, guild_id: i128) -> GuildRole {
+        GuildRole {
+            id,
+            guild_id,
+            name: "role".into(),
+            color: 0,
+            position: 1,
+        }
+    }
+
+    /// Guild 1 with default role 10 and role 11, guild 2 with default role 20 and role 21, the member is in both
+    fn two_guilds() -> Rows {
+        Rows {
+            roles: vec![role(10, 1), role(11, 1), role(20, 2), role(21, 2)],
+            members: vec![(OWNER, 1), (OWNER, 2), (MEMBER, 1), (MEMBER, 2)],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn owner_has_every_permission_without_roles() {
+        let rows = two_guilds();
+
+        let permissions = effective_permissions(&rows, OWNER, &guild(1, 10), Some(100));
+
+        assert!(permissions.has(&Permission::Write));
+        assert!(permissions.has(&Permission::ManageRoles));
+    }
+
+    #[test]
+    fn assigned_role_grants_its_permissions() {
+        let mut rows = two_guilds();
+        rows.assigned.push(GuildMemberRole {
+            user_id: MEMBER,
+            role_id: 11,
+        });
+        rows.permissions.push((11, Permission::Write));
+
+        let permissions = effective_permissions(&rows, MEMBER, &guild(1, 10), Some(100));
+
+        assert!(permissions.has(&Permission::Write));
+        assert!(!permissions.has(&Permission::ManageMessages));
+    }
+
+    #[test]
+    fn role_of_another_guild_grants_nothing() {
+        let mut rows = two_guilds();
+        rows.assigned.push(GuildMemberRole {
+            user_id: MEMBER,
+            role_id: 21,
+        });
+        rows.permissions.push((21, Permission::Write));
+        rows.permissions.push((21, Permission::Administrator));
+
+        let permissions = effective_permissions(&rows, MEMBER, &guild(1, 10), Some(100));
+
+        assert!(!permissions.has(&Permission::Write));
+    }
+
+    #[test]
+    fn default_role_is_held_only_by_members() {
+        let mut rows = two_guilds();
+        rows.permissions.push((10, Permission::Read));
+        let outsider = Identity::from_byte_array([3; 32]);
+
+        assert!(effective_permissions(&rows, MEMBER, &guild(1, 10), None).has(&Permission::Read));
+        assert!(!effective_permissions(&rows, outsider, &guild(1, 10), None).has(&Permission::Read));
+    }
+
+    #[test]
+    fn assigned_role_grants_nothing_to_non_members() {
+        let mut rows = two_guilds();
+        let outsider = Identity::from_byte_array([3; 32]);
+        rows.assigned.push(GuildMemberRole {
+            user_id: outsider,
+            role_id: 11,
+        });
+        rows.permissions.push((11, Permission::Write));
+        rows.overwrites.push(ChannelOverwrite {
+            id: 1,
+            channel_id: 100,
+            target: OverwriteTarget::Member(outsider),
+            permission: Permission::Read,
+            allow: true,
+        });
+
+        let permissions = effective_permissions(&rows, outsider, &guild(1, 10), Some(100));
+
+        assert!(!permissions.has(&Permission::Write));
+        assert!(!permissions.has(&Permission::Read));
+        assert!(member_roles(&rows, outsider, &guild(1, 10)).is_empty());
+    }
+
+    #[test]
+    fn overwrites_apply_only_to_their_channel_and_target() {
+        let mut rows = two_guilds();
+        rows.permissions.push((10, Permission::Write));
+        rows.overwrites.push(ChannelOverwrite {
+            id: 1,
+            channel_id: 100,
+            target: OverwriteTarget::Member(MEMBER),
+            permission: Permission::Write,
+            allow: false,
+        });
+
+        assert!(
+            !effective_permissions(&rows, MEMBER, &guild(1, 10), Some(100)).has(&Permission::Write)
+        );
+        assert!(
+            effective_permissions(&rows, MEMBER, &guild(1, 10), Some(101)).has(&Permission::Write)
+        );
+        assert!(
+            effective_permissions(&rows, OWNER, &guild(1, 10), Some(100)).has(&Permission::Write)
+        );
+    }
+}
@@ -1,13 +1,59 @@
 use spacetimedb::{Identity, SpacetimeType};
 
-#[derive(SpacetimeType, PartialEq)]
+/// A permission granted by a role in the whole guild, channels can overwrite it through `channel_overwrite`
+#[derive(SpacetimeType, Clone, Debug, PartialEq)]
 pub enum Permission {
+    /// Read the channels of the guild
+    Read,
+    /// Write to the channels of the guild
+    Write,
+    /// React to messages in the channels of the guild
+    React,
+    /// Delete messages of other members in every channel of the guild
+    ManageMessages,
+    /// Create and delete channels of the guild
+    ManageChannels,
+    /// Create, edit and remove roles and assign them to members
+    ManageRoles,
+    /// Remove members from the guild
+    KickMembers,
+    /// Ban and unban users from the guild
+    BanMembers,
+    /// Mention every member of the guild at once with `@everyone`
+    MentionEveryone,
+    /// Create invites to the guild
+    CreateInvites,
+    /// Every permission in every channel of the guild
+    Administrator,
+}
+
+/// Layout of the original `Permission`, stored by the original `guild_permission` table
+#[derive(SpacetimeType)]
+pub enum LegacyPermission {
     /// Read guild channel by id
     Read(i128),
     /// Write to guild channel by id
     Write(i128),
 }
 
+/// Who a channel overwrite applies to
+#[derive(SpacetimeType, Clone, Copy, Debug, PartialEq)]
+pub enum OverwriteTarget {
+    /// Every member that has the role by id
+    Role(i128),
+    /// A single member of the guild
+    Member(Identity),
+}
+
+/// Which table a message id refers to
+#[derive(SpacetimeType, Clone, Copy, PartialEq)]
+pub enum MessageKind {
+    /// A row of the `channel_message` table
+    Channel,
+    /// A row of the `guild_channel_message` table
+    Guild,
+}
+
 #[derive(SpacetimeType)]
 pub struct TwoUsers {
     pub id_a: Identity,
@@ -5,3 +5,7 @@ pub fn validate_name(name: &str) -> bool {
 pub fn validate_message(message: &str) -> bool {
     !message.is_empty()
 }
+
+pub fn validate_emoji(emoji: &str) -> bool {
+    !emoji.is_empty() && !emoji.contains(char::is_whitespace)
+}